@@ -1,5 +1,3 @@
-#![feature(ascii_char)]
-#![feature(ascii_char_variants)]
 #![feature(path_file_prefix)]
 
 use ::fs::*;
@@ -7,9 +5,8 @@ use std::fs;
 use std::io::{self, Read};
 use std::path::Path;
 use std::{
-    ascii,
     fs::{read_dir, File, OpenOptions},
-    io::{ErrorKind, Seek, SeekFrom, Write},
+    io::{Seek, SeekFrom, Write},
     slice,
 };
 use walkdir::WalkDir;
@@ -52,127 +49,141 @@ fn main() {
 
     copy_elf_files(&Path::new(ELF_SOURCE), &Path::new(SHARED_FILES)).unwrap();
 
-    let mut img = OpenOptions::new()
+    let img = OpenOptions::new()
         .create(true)
         .read(true)
         .write(true)
         .truncate(true)
         .open("fs.img")
         .unwrap();
-    let shared_files = Path::new(SHARED_FILES);
-    let dir = read_dir(shared_files).unwrap();
-    let mut current_file_id: FileId = 1;
-    let mut current_node_id: NodeId = 1;
-    for entry in dir
-        .filter_map(|e| e.ok())
-        .map(|e| e.path())
-        .filter(|e| e.is_file())
-    {
-        let mut file_data_buff: FileDataSeg = [0; 1024 - 16];
-        let mut shared_file = File::open(&entry).unwrap();
-        let file_name = String::from(entry.file_name().unwrap().to_str().unwrap());
-
-        let file_meta = get_file_meta(&shared_file, file_name, current_file_id, current_node_id);
-        // The file meta is ready, now copy its data to the disk img
-        img.seek(SeekFrom::Start(
-            (current_file_id as usize * size_of::<FileMeta>()) as u64,
-        ))
+    let mut builder = ImageBuilder {
+        img,
+        bitmap: NodeBitmap::new(),
+        next_file_id: ROOT_DIR_ID,
+    };
+    // The root is its own parent
+    let root_id = builder.add_dir(Path::new(SHARED_FILES), ROOT_DIR_ID);
+    assert_eq!(root_id, ROOT_DIR_ID);
+
+    builder
+        .img
+        .seek(SeekFrom::Start(BITMAP_OFFSET as u64))
+        .unwrap();
+    builder.img.write_all(&builder.bitmap.0).unwrap();
+    builder.img.seek(SeekFrom::Start(0)).unwrap();
+    builder
+        .img
+        .write_all(as_byte_slice(&Superblock::new()))
         .unwrap();
+    // The kernel allocates new nodes in the rest of the image
+    builder.img.set_len(DISK_SIZE as u64).unwrap();
+}
+
+/// Lays out the file tree of `shared_files` in the disk image
+struct ImageBuilder {
+    img: File,
+    bitmap: NodeBitmap,
+    next_file_id: FileId,
+}
+
+impl ImageBuilder {
+    /// Mirror the directory `path` and everything under it into the image, as a directory in
+    /// `parent`. Returns the file id of the new directory.
+    fn add_dir(&mut self, path: &Path, parent: FileId) -> FileId {
+        let file_id = self.alloc_file_id();
+        let mut children: Vec<_> = read_dir(path)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .collect();
+        children.sort();
+
+        let mut entries = Vec::new();
+        for child in children {
+            let child_id = if child.is_dir() {
+                self.add_dir(&child, file_id)
+            } else if child.is_file() {
+                let child_id = self.alloc_file_id();
+                let data = fs::read(&child).unwrap();
+                self.write_file(child_id, REGULAR_FILE, file_id, &data);
+                child_id
+            } else {
+                continue;
+            };
+            let name = child.file_name().unwrap().to_str().unwrap();
+            let entry = DirEntry::new(child_id, name).unwrap_or_else(|| {
+                panic!(
+                    "{:?} can't be a file name, it must be ASCII and at most {} bytes long",
+                    name, FILE_NAME_LEN
+                )
+            });
+            entries.extend_from_slice(as_byte_slice(&entry));
+        }
+        self.write_file(file_id, DIRECTORY, parent, &entries);
+        file_id
+    }
 
+    fn alloc_file_id(&mut self) -> FileId {
+        let file_id = self.next_file_id;
+        assert!(
+            (file_id as usize) < MAX_FILES,
+            "{} holds more files than the disk image has room for ({}, including the directories)",
+            SHARED_FILES,
+            MAX_FILES - 1
+        );
+        self.next_file_id += 1;
+        file_id
+    }
+
+    /// Write the metadata of the file, and its data to newly allocated nodes
+    fn write_file(&mut self, file_id: FileId, file_type: u16, parent: FileId, data: &[u8]) {
+        let node_ids: Vec<NodeId> = (0..nodes_for_size(data.len()))
+            .map(|_| self.bitmap.alloc().expect("The disk image is out of nodes"))
+            .collect();
+
+        let file_meta = FileMeta {
+            magic_number: FILE_MAGIC_NUMBER,
+            node_list_start: node_ids[0],
+            file_id,
+            file_type,
+            size: data.len() as u32,
+            parent,
+            generation: 0,
+            reserved: [0; 12],
+        };
+        self.img
+            .seek(SeekFrom::Start(file_meta_address(file_id) as u64))
+            .unwrap();
         let file_meta_buff = as_byte_slice(&file_meta);
         assert_eq!(file_meta_buff.len(), 32);
-        img.write_all(file_meta_buff).unwrap();
-        // The file meta has been written to the file meta section
-        // Now we need to write the actuall data of the file into the disk img
-
-        let first_node_id = current_node_id;
-        let last_node_id = first_node_id + file_meta.size / FILE_DATA_SIZE as u32;
-        loop {
-            let end = read_max(&mut shared_file, &mut file_data_buff).unwrap();
+        self.img.write_all(file_meta_buff).unwrap();
+
+        // The last node might not be full, or even have any data at all
+        let mut segments = data.chunks(FILE_DATA_SIZE);
+        for (i, &node_id) in node_ids.iter().enumerate() {
+            let mut file_data_buff: FileDataSeg = [0; FILE_DATA_SIZE];
+            if let Some(segment) = segments.next() {
+                file_data_buff[..segment.len()].copy_from_slice(segment);
+            }
             let node = Node {
                 magic_number: NODE_MAGIC_NUMBER,
                 data: file_data_buff,
-                flags: 1,
-                next_node: if current_node_id == last_node_id {
-                    first_node_id
-                } else {
-                    current_node_id + 1
-                },
-                prev_node: if current_node_id == first_node_id {
-                    last_node_id
-                } else {
-                    current_node_id - 1
-                },
-                file_id: current_file_id,
+                flags: NODE_USED,
+                next_node: node_ids[(i + 1) % node_ids.len()],
+                prev_node: node_ids[(i + node_ids.len() - 1) % node_ids.len()],
+                file_id,
             };
 
-            img.seek(SeekFrom::Start(
-                (NODES_OFFSET + current_node_id as usize * size_of::<Node>()) as u64,
-            ))
-            .unwrap();
-
+            self.img
+                .seek(SeekFrom::Start(node_address(node_id) as u64))
+                .unwrap();
             let node_buff = as_byte_slice(&node);
             assert_eq!(node_buff.len(), NODE_SIZE);
-            img.write_all(node_buff).unwrap();
-
-            current_node_id += 1;
-
-            if end {
-                break;
-            }
-        }
-
-        current_file_id += 1;
-    }
-}
-
-/// Return Ok(true) if the source has no more information to give
-fn read_max<R: Read + ?Sized>(this: &mut R, mut buf: &mut [u8]) -> std::io::Result<bool> {
-    while !buf.is_empty() {
-        match this.read(buf) {
-            Ok(0) => return Ok(true),
-            Ok(n) => {
-                buf = &mut buf[n..];
-            }
-            Err(e) if e.kind() == ErrorKind::Interrupted => {}
-            Err(e) => return Err(e),
+            self.img.write_all(node_buff).unwrap();
         }
     }
-    Ok(false)
 }
 
 fn as_byte_slice<'a, T>(t: &'a T) -> &'a [u8] {
     unsafe { slice::from_raw_parts(t as *const T as *const u8, size_of::<T>()) }
 }
-
-fn get_file_meta(
-    file: &File,
-    file_name: String,
-    file_id: FileId,
-    current_node_id: NodeId,
-) -> FileMeta {
-    let mut file_name_buff: [ascii::Char; FILE_NAME_LEN] = [ascii::Char::Null; FILE_NAME_LEN];
-    if file_name.len() > FILE_NAME_LEN {
-        panic!(
-            "File name {} too long, must be shorter than {}",
-            file_name, FILE_NAME_LEN
-        );
-    }
-    for (i, char) in file_name
-        .as_ascii()
-        .expect("file name needs to be ascii compatible")
-        .into_iter()
-        .enumerate()
-    {
-        file_name_buff[i] = *char;
-    }
-    let file_size = file.metadata().unwrap().len() as u32;
-
-    FileMeta {
-        magic_number: FILE_MAGIC_NUMBER,
-        file_id,
-        size: file_size,
-        name: file_name_buff,
-        node_list_start: current_node_id,
-    }
-}
@@ -1,18 +1,41 @@
-#![feature(ascii_char)]
-#![feature(ascii_char_variants)]
 #![no_std]
 #![no_main]
 
-use core::ascii;
-
 pub const SECTOR_SIZE: usize = 512;
 pub const NODE_MAGIC_NUMBER: u32 = 102030069;
 pub const FILE_MAGIC_NUMBER: u32 = 900000111;
 pub const MAX_FILES: usize = NODE_SIZE;
-pub const NODES_OFFSET: usize = size_of::<FileMeta>() * MAX_FILES;
-pub const FILE_NAME_LEN: usize = 18;
+/// The first block of the disk holds the [`Superblock`], the file metadata comes after it
+pub const FILE_TABLE_OFFSET: usize = BLOCK_SIZE;
+/// The free-node bitmap comes right after the file metadata, see [`NodeBitmap`]
+pub const BITMAP_OFFSET: usize = FILE_TABLE_OFFSET + size_of::<FileMeta>() * MAX_FILES;
+pub const BITMAP_SIZE: usize = MAX_NODES / 8;
+pub const NODES_OFFSET: usize = BITMAP_OFFSET + BITMAP_SIZE;
+/// The amount of bytes the disk reads and writes at once
+pub const BLOCK_SIZE: usize = 1024;
+/// The longest name a directory entry can hold
+pub const FILE_NAME_LEN: usize = 30;
 pub const NODE_SIZE: usize = 1024;
 pub const FILE_DATA_SIZE: usize = NODE_SIZE - 16;
+/// The amount of nodes the disk has room for, node 0 is never used
+pub const MAX_NODES: usize = 16 * 1024;
+/// The size of the disk image in bytes
+pub const DISK_SIZE: usize = NODES_OFFSET + MAX_NODES * NODE_SIZE;
+/// Set in `Node::flags` of the nodes that belong to a file. Which nodes are free is decided by
+/// the [`NodeBitmap`], not by this flag.
+pub const NODE_USED: u16 = 1;
+/// The file id of the root directory `/`
+pub const ROOT_DIR_ID: FileId = 1;
+/// `FileMeta::file_type` of a file that holds data
+pub const REGULAR_FILE: u16 = 1;
+/// `FileMeta::file_type` of a file that holds an array of [`DirEntry`]
+pub const DIRECTORY: u16 = 2;
+pub const SUPERBLOCK_MAGIC_NUMBER: u32 = 0x5249_534f;
+/// The version of the layout of the disk, bumped whenever an older kernel would misread it
+pub const FS_VERSION: u32 = 1;
+/// The optional features this version knows of, as bits of `Superblock::features`. There are
+/// none yet, an image with any bit set can't be read.
+pub const SUPPORTED_FEATURES: u32 = 0;
 /// The address of a node with some NodeId is: (NODES_OFFSET + size_of::<Node>() * NodeId)
 pub type NodeId = u32;
 pub type FileId = u16;
@@ -20,11 +43,55 @@ pub type FileId = u16;
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct FileMeta {
-    pub magic_number: u32,                  // 4 bytes, Always =FILE_MAGIC_NUMBER
-    pub node_list_start: NodeId,            // 4 bytes, the index of the node
-    pub file_id: FileId,                    // 2 bytes
-    pub name: [ascii::Char; FILE_NAME_LEN], // 18 bytes
-    pub size: u32,                          // 4 bytes, size in bytes
+    pub magic_number: u32,       // 4 bytes, Always =FILE_MAGIC_NUMBER
+    pub node_list_start: NodeId, // 4 bytes, the index of the node
+    pub file_id: FileId,         // 2 bytes
+    pub file_type: u16,          // 2 bytes, REGULAR_FILE or DIRECTORY
+    pub size: u32,               // 4 bytes, size in bytes
+    pub parent: FileId,          // 2 bytes, the directory the file is in, the root is its own
+    pub generation: u16,         // 2 bytes, bumped every time the slot is given to a new file
+    pub reserved: [u8; 12],      // 12 bytes, always 0
+}
+
+/// An entry of a directory, the data of a directory is an array of them. Names are found
+/// only here, the file itself doesn't know what it's called.
+// Must be 32 bytes
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct DirEntry {
+    pub file_id: FileId,           // 2 bytes
+    pub name: [u8; FILE_NAME_LEN], // 30 bytes, ASCII, padded with zeros
+}
+
+impl DirEntry {
+    /// An entry for the file `file_id` called `name`. Returns `None` if `name` can't be the
+    /// name of a file: it's empty, `.` or `..`, too long, not ASCII, or has a `/` or a zero byte.
+    pub fn new(file_id: FileId, name: &str) -> Option<Self> {
+        if name.is_empty()
+            || name == "."
+            || name == ".."
+            || name.len() > FILE_NAME_LEN
+            || !name.is_ascii()
+            || name.contains(['/', '\0'])
+        {
+            return None;
+        }
+        let mut entry = DirEntry {
+            file_id,
+            name: [0; FILE_NAME_LEN],
+        };
+        entry.name[..name.len()].copy_from_slice(name.as_bytes());
+        Some(entry)
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(FILE_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
 }
 
 pub type FileDataSeg = [u8; FILE_DATA_SIZE];
@@ -40,6 +107,111 @@ pub struct Node {
     pub data: FileDataSeg,
 }
 
+/// Describes the layout of the image, it's found at the start of sector 0
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Superblock {
+    pub magic_number: u32, // Always =SUPERBLOCK_MAGIC_NUMBER
+    pub version: u32,      // FS_VERSION of the builder
+    pub node_size: u32,    // NODE_SIZE
+    pub node_count: u32,   // MAX_NODES
+    pub max_files: u32,    // MAX_FILES, the size of the file table
+    pub features: u32,     // Bits of SUPPORTED_FEATURES
+}
+
+impl Superblock {
+    /// The superblock of an image built with the constants of this crate
+    pub const fn new() -> Self {
+        Superblock {
+            magic_number: SUPERBLOCK_MAGIC_NUMBER,
+            version: FS_VERSION,
+            node_size: NODE_SIZE as u32,
+            node_count: MAX_NODES as u32,
+            max_files: MAX_FILES as u32,
+            features: 0,
+        }
+    }
+
+    /// Check that an image with this superblock can be read with the constants of this crate.
+    /// Returns what doesn't match otherwise.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let expected = Superblock::new();
+        if self.magic_number != expected.magic_number {
+            return Err("not a file system image");
+        }
+        if self.version != expected.version {
+            return Err("unsupported format version");
+        }
+        if self.node_size != expected.node_size {
+            return Err("unsupported node size");
+        }
+        if self.node_count != expected.node_count {
+            return Err("unsupported node count");
+        }
+        if self.max_files > expected.max_files {
+            return Err("more files than supported");
+        }
+        if self.max_files != expected.max_files {
+            return Err("unsupported file table size");
+        }
+        if self.features & !SUPPORTED_FEATURES != 0 {
+            return Err("unsupported features");
+        }
+        Ok(())
+    }
+}
+
+impl Default for Superblock {
+    fn default() -> Self {
+        Superblock::new()
+    }
+}
+
+/// Which nodes are in use, bit `node_id % 8` of byte `node_id / 8` is set if the node is taken.
+/// Node 0 is never used, so it's always taken.
+#[repr(transparent)]
+pub struct NodeBitmap(pub [u8; BITMAP_SIZE]);
+
+impl NodeBitmap {
+    /// A bitmap where every node is free
+    pub const fn new() -> Self {
+        let mut bits = [0; BITMAP_SIZE];
+        bits[0] = 1;
+        NodeBitmap(bits)
+    }
+
+    pub fn is_used(&self, node_id: NodeId) -> bool {
+        self.0[node_id as usize / 8] & (1 << (node_id % 8)) != 0
+    }
+
+    /// Take the free node with the lowest id, `None` if every node is taken
+    pub fn alloc(&mut self) -> Option<NodeId> {
+        let byte = self.0.iter().position(|&byte| byte != u8::MAX)?;
+        let node_id = (byte * 8 + self.0[byte].trailing_ones() as usize) as NodeId;
+        self.0[byte] |= 1 << (node_id % 8);
+        Some(node_id)
+    }
+
+    pub fn free(&mut self, node_id: NodeId) {
+        assert!(node_id != 0 && self.is_used(node_id));
+        self.0[node_id as usize / 8] &= !(1 << (node_id % 8));
+    }
+
+    /// The block of the bitmap that holds the bit of `node_id`, and its address on the disk. Write
+    /// it back after the node is allocated or freed.
+    pub fn block_of(&self, node_id: NodeId) -> (usize, &[u8; BLOCK_SIZE]) {
+        let offset = node_id as usize / 8 / BLOCK_SIZE * BLOCK_SIZE;
+        let block = self.0[offset..offset + BLOCK_SIZE].try_into().unwrap();
+        (BITMAP_OFFSET + offset, block)
+    }
+}
+
+impl Default for NodeBitmap {
+    fn default() -> Self {
+        NodeBitmap::new()
+    }
+}
+
 const _: () = {
     if core::mem::size_of::<Node>() != NODE_SIZE {
         panic!()
@@ -47,8 +219,30 @@ const _: () = {
     if core::mem::size_of::<FileMeta>() != 32 {
         panic!()
     }
+    if core::mem::size_of::<DirEntry>() != 32 {
+        panic!()
+    }
+    if core::mem::size_of::<Superblock>() > SECTOR_SIZE {
+        panic!()
+    }
+    // The file table and the bitmap are read and written in whole blocks
+    if !FILE_TABLE_OFFSET.is_multiple_of(BLOCK_SIZE) {
+        panic!()
+    }
+    if !BITMAP_OFFSET.is_multiple_of(BLOCK_SIZE) || !BITMAP_SIZE.is_multiple_of(BLOCK_SIZE) {
+        panic!()
+    }
 };
 
 pub const fn node_address(node_id: NodeId) -> usize {
     NODES_OFFSET + size_of::<Node>() * node_id as usize
 }
+
+pub const fn file_meta_address(file_id: FileId) -> usize {
+    FILE_TABLE_OFFSET + size_of::<FileMeta>() * file_id as usize
+}
+
+/// The amount of nodes a file of `size` bytes takes, every file has at least one
+pub const fn nodes_for_size(size: usize) -> usize {
+    size / FILE_DATA_SIZE + 1
+}
@@ -1,27 +1,64 @@
 use crate::{
     param::{ProcId, NCPU},
-    proc::{cpuid, procs, Process},
+    proc::{cpuid, procs, Context, Process},
+    scheduler::RunQueue,
 };
+use core::{
+    ptr::addr_of,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use spin::Mutex;
 
-#[derive(Clone, Copy)]
 pub struct CPU {
+    /// The process the hart is running, or the last one it ran if it's in its scheduler
     pub current_proc: ProcId,
+    /// Is the hart running [`CPU::current_proc`] right now (and not booting or scheduling)
+    pub running_proc: bool,
+    /// The saved kernel registers of the hart's scheduler, a running process switches here to
+    /// give up the hart.
+    pub context: Context,
+    /// The runnable processes that wait for this hart. Other harts push to it when they make
+    /// a process runnable, and steal from it when they are idle. Must only be locked with
+    /// interrupts disabled.
+    pub run_queue: Mutex<RunQueue>,
+    /// Has the hart booted and started scheduling processes
+    pub online: AtomicBool,
 }
 
-pub static mut CURRENT_CPUS: [CPU; NCPU] = [CPU::new(); NCPU];
+pub static mut CURRENT_CPUS: [CPU; NCPU] = [const { CPU::new() }; NCPU];
 
 impl CPU {
     const fn new() -> Self {
-        CPU { current_proc: 0 }
+        CPU {
+            current_proc: 0,
+            running_proc: false,
+            context: Context::zeroed(),
+            run_queue: Mutex::new(RunQueue::new()),
+            online: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::SeqCst)
     }
 }
 
+/// All of the cpus, indexed by hart id
+pub fn cpus<'a>() -> &'a [CPU; NCPU] {
+    unsafe { &*addr_of!(CURRENT_CPUS) }
+}
+
 /// Current cpu
 pub fn ccpu<'a>() -> &'a mut CPU {
     // SAFETY: guarnteed to only be accessed from one CPU at a time
     unsafe { &mut CURRENT_CPUS[cpuid()] }
 }
 
+/// Is the current cpu running a process, see [`CPU::running_proc`]
+pub fn in_proc() -> bool {
+    ccpu().running_proc
+}
+
 /// Current running process
 pub fn cproc<'a>() -> &'a Process {
     &procs()[ccpu().current_proc]
@@ -8,11 +8,14 @@ pub struct ParsedExecutable<'a> {
     pub entry_point: usize,
 }
 
-/// The file_data must be the complete, uncut elf file
+/// The file_data must be the complete, uncut elf file.
+/// Returns `None` if the file isn't a valid RISC-V elf file.
 pub fn parse_executable_file<'f>(file_data: &'f [u8]) -> Option<ParsedExecutable<'f>> {
     let elf_bytes = ElfBytes::<AnyEndian>::minimal_parse(file_data).ok()?;
     let segs = elf_bytes.segments()?;
-    assert_eq!(elf_bytes.ehdr.e_machine, RISCV_E_MACHINE);
+    if elf_bytes.ehdr.e_machine != RISCV_E_MACHINE {
+        return None;
+    }
     // #[cfg(debug_assertions)]
     // cprintln!("{:#?}", elf_bytes.ehdr);
     // #[cfg(debug_assertions)]
@@ -0,0 +1,112 @@
+use core::fmt;
+
+/// An error that a syscall returns to user mode. The kernel puts `-errno` in `a0`, so a
+/// return value in the last 4095 values of `usize` is an error, and anything else is a result.
+/// The numbers are the ones Linux uses.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Errno(pub usize);
+
+impl Errno {
+    /// No such file or directory
+    pub const ENOENT: Errno = Errno(2);
+    /// No such process
+    pub const ESRCH: Errno = Errno(3);
+    /// Input/output error
+    pub const EIO: Errno = Errno(5);
+    /// Argument list too long
+    pub const E2BIG: Errno = Errno(7);
+    /// Exec format error
+    pub const ENOEXEC: Errno = Errno(8);
+    /// Bad file descriptor
+    pub const EBADF: Errno = Errno(9);
+    /// No child processes
+    pub const ECHILD: Errno = Errno(10);
+    /// Resource temporarily unavailable
+    pub const EAGAIN: Errno = Errno(11);
+    /// Out of memory
+    pub const ENOMEM: Errno = Errno(12);
+    /// Bad address
+    pub const EFAULT: Errno = Errno(14);
+    /// File exists
+    pub const EEXIST: Errno = Errno(17);
+    /// Not a directory
+    pub const ENOTDIR: Errno = Errno(20);
+    /// Is a directory
+    pub const EISDIR: Errno = Errno(21);
+    /// Invalid argument
+    pub const EINVAL: Errno = Errno(22);
+    /// Too many open files
+    pub const EMFILE: Errno = Errno(24);
+    /// File too large
+    pub const EFBIG: Errno = Errno(27);
+    /// No space left on device
+    pub const ENOSPC: Errno = Errno(28);
+    /// Illegal seek
+    pub const ESPIPE: Errno = Errno(29);
+    /// Read-only file system
+    pub const EROFS: Errno = Errno(30);
+    /// File name too long
+    pub const ENAMETOOLONG: Errno = Errno(36);
+    /// Function not implemented
+    pub const ENOSYS: Errno = Errno(38);
+    /// Directory not empty
+    pub const ENOTEMPTY: Errno = Errno(39);
+
+    /// The largest errno, values of `a0` from `-MAX_ERRNO` up are errors
+    pub const MAX_ERRNO: usize = 4095;
+
+    /// Encode the result of a syscall into the value that is returned in `a0`
+    pub fn encode(res: Result<usize, Errno>) -> usize {
+        match res {
+            Ok(val) => val,
+            Err(Errno(errno)) => errno.wrapping_neg(),
+        }
+    }
+
+    /// Decode the value a syscall returned in `a0`
+    pub fn decode(ret: usize) -> Result<usize, Errno> {
+        if ret >= Self::MAX_ERRNO.wrapping_neg() {
+            Err(Errno(ret.wrapping_neg()))
+        } else {
+            Ok(ret)
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Errno::ENOENT => "ENOENT",
+            Errno::ESRCH => "ESRCH",
+            Errno::EIO => "EIO",
+            Errno::E2BIG => "E2BIG",
+            Errno::ENOEXEC => "ENOEXEC",
+            Errno::EBADF => "EBADF",
+            Errno::ECHILD => "ECHILD",
+            Errno::EAGAIN => "EAGAIN",
+            Errno::ENOMEM => "ENOMEM",
+            Errno::EFAULT => "EFAULT",
+            Errno::EEXIST => "EEXIST",
+            Errno::ENOTDIR => "ENOTDIR",
+            Errno::EISDIR => "EISDIR",
+            Errno::EINVAL => "EINVAL",
+            Errno::EMFILE => "EMFILE",
+            Errno::EFBIG => "EFBIG",
+            Errno::ENOSPC => "ENOSPC",
+            Errno::ESPIPE => "ESPIPE",
+            Errno::EROFS => "EROFS",
+            Errno::ENAMETOOLONG => "ENAMETOOLONG",
+            Errno::ENOSYS => "ENOSYS",
+            Errno::ENOTEMPTY => "ENOTEMPTY",
+            _ => "EUNKNOWN",
+        }
+    }
+}
+
+impl fmt::Debug for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name(), self.0)
+    }
+}
+
+/// What a syscall handler returns, see [`Errno::encode`]
+pub type SysResult = Result<usize, Errno>;
@@ -0,0 +1,138 @@
+//! Directories and paths. A directory is a file of type [`DIRECTORY`] whose data is an array of
+//! [`DirEntry`], without holes. Callers hold the disk lock.
+
+use super::{
+    file_meta, file_meta_of, read_locked, truncate_locked, write_locked, DirEntry, FileMeta,
+    FileRef, NodeAllocator, DIRECTORY, FILE_NAME_LEN, ROOT_DIR_ID,
+};
+use crate::errno::Errno;
+use core::slice;
+
+pub const EMPTY_DIR_ENTRY: DirEntry = DirEntry {
+    file_id: 0,
+    name: [0; FILE_NAME_LEN],
+};
+
+/// Find the file at `path`. A path that starts with a `/` starts at the root directory, any
+/// other path starts at `cwd`. `.` and `..` are the directory itself and its parent.
+pub(super) fn resolve(cwd: FileRef, path: &str) -> Result<FileMeta, Errno> {
+    let mut current = if path.starts_with('/') {
+        file_meta(ROOT_DIR_ID)?
+    } else {
+        file_meta_of(cwd)?
+    };
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        if current.file_type != DIRECTORY {
+            return Err(Errno::ENOTDIR);
+        }
+        current = match name {
+            "." => current,
+            ".." => file_meta(current.parent)?,
+            name => {
+                let (_, entry) = find_entry(&current, name)?.ok_or(Errno::ENOENT)?;
+                file_meta(entry.file_id)?
+            }
+        };
+    }
+    Ok(current)
+}
+
+/// Split `path` into the directory it's in and its last name, for creating and deleting
+/// files. The name can't be `.` or `..`, so the root can't be created or deleted.
+pub(super) fn resolve_parent(cwd: FileRef, path: &str) -> Result<(FileMeta, &str), Errno> {
+    let path = path.trim_end_matches('/');
+    let (dir_path, name) = match path.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((dir_path, name)) => (dir_path, name),
+        None => ("", path),
+    };
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(Errno::EINVAL);
+    }
+    let dir = resolve(cwd, dir_path)?;
+    if dir.file_type != DIRECTORY {
+        return Err(Errno::ENOTDIR);
+    }
+    Ok((dir, name))
+}
+
+/// An entry called `name`, for a file that doesn't have an id yet. Returns `ENAMETOOLONG` if
+/// the name doesn't fit, and `EINVAL` if it can't be a file name.
+pub(super) fn new_entry(name: &str) -> Result<DirEntry, Errno> {
+    if name.len() > FILE_NAME_LEN {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    DirEntry::new(0, name).ok_or(Errno::EINVAL)
+}
+
+/// Read the entries of the directory into `entries`, starting at entry number `index`.
+/// Returns how many were read.
+pub(super) fn read_entries(
+    dir: &FileMeta,
+    index: usize,
+    entries: &mut [DirEntry],
+) -> Result<usize, Errno> {
+    let buf =
+        unsafe { slice::from_raw_parts_mut(entries.as_mut_ptr() as *mut u8, size_of_val(entries)) };
+    let read = read_locked(dir, index.saturating_mul(size_of::<DirEntry>()), buf)?;
+    Ok(read / size_of::<DirEntry>())
+}
+
+/// Find the entry called `name` in the directory. Returns its index and the entry.
+pub(super) fn find_entry(dir: &FileMeta, name: &str) -> Result<Option<(usize, DirEntry)>, Errno> {
+    let mut entries = [EMPTY_DIR_ENTRY; 32];
+    let mut index = 0;
+    loop {
+        let read = read_entries(dir, index, &mut entries)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        if let Some(i) = entries[..read]
+            .iter()
+            .position(|entry| entry.name() == name)
+        {
+            return Ok(Some((index + i, entries[i])));
+        }
+        index += read;
+    }
+}
+
+/// Add `entry` to the end of the directory
+pub(super) fn add_entry(
+    disk_fs: &mut NodeAllocator,
+    dir: &FileMeta,
+    entry: &DirEntry,
+) -> Result<(), Errno> {
+    write_locked(
+        disk_fs,
+        dir.file_id,
+        dir.size as usize,
+        entry_bytes(slice::from_ref(entry)),
+    )?;
+    Ok(())
+}
+
+/// Remove entry number `index` from the directory. The last entry takes its place, so the
+/// directory has no holes.
+pub(super) fn remove_entry(
+    disk_fs: &mut NodeAllocator,
+    dir: &FileMeta,
+    index: usize,
+) -> Result<(), Errno> {
+    let last = dir.size as usize / size_of::<DirEntry>() - 1;
+    if index != last {
+        let mut entry = [EMPTY_DIR_ENTRY];
+        read_entries(dir, last, &mut entry)?;
+        write_locked(
+            disk_fs,
+            dir.file_id,
+            index * size_of::<DirEntry>(),
+            entry_bytes(&entry),
+        )?;
+    }
+    truncate_locked(disk_fs, dir.file_id, last * size_of::<DirEntry>())
+}
+
+fn entry_bytes(entries: &[DirEntry]) -> &[u8] {
+    unsafe { slice::from_raw_parts(entries.as_ptr() as *const u8, size_of_val(entries)) }
+}
@@ -0,0 +1,282 @@
+use super::{
+    append_file, create_file, file_meta_of, lookup, read_dir, read_file_at, truncate_file,
+    write_file_at, DirEntry, FileRef, DIRECTORY,
+};
+use crate::{cprint, errno::Errno, keyboard::read_keys, param::NOFILE};
+use alloc::{string::String, sync::Arc};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The file descriptor of the console input
+pub const STDIN: usize = 0;
+/// The file descriptor of the console output
+pub const STDOUT: usize = 1;
+/// The file descriptor of the console error output
+pub const STDERR: usize = 2;
+
+/// `open` flags
+pub const O_RDONLY: usize = 0;
+pub const O_WRONLY: usize = 1;
+pub const O_RDWR: usize = 2;
+const O_ACCMODE: usize = 3;
+/// Create the file if it doesn't exist
+pub const O_CREAT: usize = 0x40;
+/// Truncate the file to 0 bytes if it's opened for writing
+pub const O_TRUNC: usize = 0x200;
+/// Every write goes to the end of the file
+pub const O_APPEND: usize = 0x400;
+
+/// `lseek` origins: the start of the file, the current offset, and the end of the file
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(usize)]
+pub enum FileType {
+    Console = 0,
+    Regular = 1,
+    Directory = 2,
+}
+
+/// What `fstat` copies into user memory
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct Stat {
+    /// The id of the file on the disk, 0 for the console
+    pub file_id: usize,
+    pub file_type: FileType,
+    /// The size of the file in bytes, 0 for the console
+    pub size: usize,
+}
+
+enum OpenFileKind {
+    Console,
+    Disk(FileRef),
+    /// Read with [`OpenFile::read_dir`], the offset is in bytes like for any other file
+    Dir(FileRef),
+}
+
+/// A file that was opened by a process. Forked processes share their open files, and with them
+/// the offset.
+pub struct OpenFile {
+    kind: OpenFileKind,
+    readable: bool,
+    writable: bool,
+    append: bool,
+    offset: AtomicUsize,
+}
+
+impl OpenFile {
+    pub fn console(readable: bool, writable: bool) -> Self {
+        OpenFile {
+            kind: OpenFileKind::Console,
+            readable,
+            writable,
+            append: false,
+            offset: AtomicUsize::new(0),
+        }
+    }
+
+    /// Open the file `path` from the disk, relative to the directory `cwd`. `flags` is one of
+    /// [`O_RDONLY`], [`O_WRONLY`] and [`O_RDWR`], combined with any of [`O_CREAT`],
+    /// [`O_TRUNC`] and [`O_APPEND`]. Directories can only be opened for reading.
+    pub fn open(cwd: FileRef, path: &str, flags: usize) -> Result<Self, Errno> {
+        let (readable, writable) = match flags & O_ACCMODE {
+            O_RDONLY => (true, false),
+            O_WRONLY => (false, true),
+            O_RDWR => (true, true),
+            _ => return Err(Errno::EINVAL),
+        };
+        if flags & !(O_ACCMODE | O_CREAT | O_TRUNC | O_APPEND) != 0 {
+            return Err(Errno::EINVAL);
+        }
+        let file_meta = match lookup(cwd, path) {
+            Err(Errno::ENOENT) if flags & O_CREAT != 0 => match create_file(cwd, path) {
+                // Someone else created it first
+                Err(Errno::EEXIST) => lookup(cwd, path)?,
+                res => res?,
+            },
+            res => res?,
+        };
+        let file = FileRef::of(&file_meta);
+        let kind = if file_meta.file_type == DIRECTORY {
+            if writable {
+                return Err(Errno::EISDIR);
+            }
+            OpenFileKind::Dir(file)
+        } else {
+            OpenFileKind::Disk(file)
+        };
+        if writable && flags & O_TRUNC != 0 {
+            truncate_file(file, 0)?;
+        }
+        Ok(OpenFile {
+            kind,
+            readable,
+            writable,
+            append: flags & O_APPEND != 0,
+            offset: AtomicUsize::new(0),
+        })
+    }
+
+    /// Read from the current offset into `buf`, and move the offset past what was read.
+    /// Returns how many bytes were read, 0 at the end of the file. Reading the console blocks
+    /// until a key is pressed, and returns at most a line.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if !self.readable {
+            return Err(Errno::EBADF);
+        }
+        match &self.kind {
+            OpenFileKind::Console => Ok(read_keys(buf)),
+            OpenFileKind::Disk(file) => {
+                let offset = self.offset.load(Ordering::SeqCst);
+                let read = read_file_at(*file, offset, buf)?;
+                self.offset.store(offset + read, Ordering::SeqCst);
+                Ok(read)
+            }
+            OpenFileKind::Dir(_) => Err(Errno::EISDIR),
+        }
+    }
+
+    /// Read the entries of a directory into `entries`, from the current offset, and move the
+    /// offset past them. Returns how many entries were read, 0 once there are no more.
+    pub fn read_dir(&self, entries: &mut [DirEntry]) -> Result<usize, Errno> {
+        let OpenFileKind::Dir(file) = &self.kind else {
+            return Err(Errno::ENOTDIR);
+        };
+        let offset = self.offset.load(Ordering::SeqCst);
+        let read = read_dir(*file, offset / size_of::<DirEntry>(), entries)?;
+        self.offset
+            .store(offset + read * size_of::<DirEntry>(), Ordering::SeqCst);
+        Ok(read)
+    }
+
+    /// Write `buf` at the current offset, or at the end of the file if it was opened with
+    /// [`O_APPEND`], and move the offset past what was written. Returns how many bytes were
+    /// written.
+    pub fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        if !self.writable {
+            return Err(Errno::EBADF);
+        }
+        match &self.kind {
+            OpenFileKind::Console => {
+                cprint!("{}", String::from_utf8_lossy(buf));
+                Ok(buf.len())
+            }
+            OpenFileKind::Disk(file) if self.append => {
+                let new_size = append_file(*file, buf)?;
+                self.offset.store(new_size, Ordering::SeqCst);
+                Ok(buf.len())
+            }
+            OpenFileKind::Disk(file) => {
+                let offset = self.offset.load(Ordering::SeqCst);
+                let written = write_file_at(*file, offset, buf)?;
+                self.offset.store(offset + written, Ordering::SeqCst);
+                Ok(written)
+            }
+            // Directories can't be opened for writing
+            OpenFileKind::Dir(_) => Err(Errno::EISDIR),
+        }
+    }
+
+    /// Move the offset to `offset` bytes from `whence`, one of [`SEEK_SET`], [`SEEK_CUR`] and
+    /// [`SEEK_END`]. Returns the new offset, the console can't seek.
+    pub fn seek(&self, offset: isize, whence: usize) -> Result<usize, Errno> {
+        let (OpenFileKind::Disk(file) | OpenFileKind::Dir(file)) = &self.kind else {
+            return Err(Errno::ESPIPE);
+        };
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => self.offset.load(Ordering::SeqCst),
+            SEEK_END => file_meta_of(*file)?.size as usize,
+            _ => return Err(Errno::EINVAL),
+        };
+        let new_offset = base.checked_add_signed(offset).ok_or(Errno::EINVAL)?;
+        self.offset.store(new_offset, Ordering::SeqCst);
+        Ok(new_offset)
+    }
+
+    /// Change the size of the file to `size`, see [`truncate_file`]. The file must be open for
+    /// writing.
+    pub fn truncate(&self, size: usize) -> Result<(), Errno> {
+        match &self.kind {
+            OpenFileKind::Disk(file) if self.writable => truncate_file(*file, size),
+            OpenFileKind::Disk(_) => Err(Errno::EBADF),
+            OpenFileKind::Dir(_) => Err(Errno::EISDIR),
+            OpenFileKind::Console => Err(Errno::EINVAL),
+        }
+    }
+
+    pub fn stat(&self) -> Result<Stat, Errno> {
+        Ok(match &self.kind {
+            OpenFileKind::Console => Stat {
+                file_id: 0,
+                file_type: FileType::Console,
+                size: 0,
+            },
+            OpenFileKind::Disk(file) => Stat {
+                file_id: file.file_id as usize,
+                file_type: FileType::Regular,
+                size: file_meta_of(*file)?.size as usize,
+            },
+            OpenFileKind::Dir(file) => Stat {
+                file_id: file.file_id as usize,
+                file_type: FileType::Directory,
+                size: file_meta_of(*file)?.size as usize,
+            },
+        })
+    }
+}
+
+/// The open files of a process, indexed by file descriptor
+#[derive(Clone)]
+pub struct FdTable([Option<Arc<OpenFile>>; NOFILE]);
+
+impl FdTable {
+    pub const fn new() -> Self {
+        FdTable([const { None }; NOFILE])
+    }
+
+    /// A table with [`STDIN`], [`STDOUT`] and [`STDERR`] open on the console
+    pub fn with_console() -> Self {
+        let mut fds = FdTable::new();
+        fds.0[STDIN] = Some(Arc::new(OpenFile::console(true, false)));
+        fds.0[STDOUT] = Some(Arc::new(OpenFile::console(false, true)));
+        fds.0[STDERR] = Some(Arc::new(OpenFile::console(false, true)));
+        fds
+    }
+
+    pub fn get(&self, fd: usize) -> Result<Arc<OpenFile>, Errno> {
+        self.0.get(fd).cloned().flatten().ok_or(Errno::EBADF)
+    }
+
+    /// Put the file in the lowest free descriptor, and return it
+    pub fn alloc(&mut self, file: OpenFile) -> Result<usize, Errno> {
+        let fd = self
+            .0
+            .iter()
+            .position(Option::is_none)
+            .ok_or(Errno::EMFILE)?;
+        self.0[fd] = Some(Arc::new(file));
+        Ok(fd)
+    }
+
+    pub fn close(&mut self, fd: usize) -> Result<(), Errno> {
+        self.0
+            .get_mut(fd)
+            .and_then(Option::take)
+            .map(|_| ())
+            .ok_or(Errno::EBADF)
+    }
+
+    /// Close every file
+    pub fn clear(&mut self) {
+        *self = FdTable::new();
+    }
+}
+
+impl Default for FdTable {
+    fn default() -> Self {
+        FdTable::new()
+    }
+}
@@ -1,12 +1,22 @@
-use crate::{cprint, cprintln, mem::paging::Page, param::PAGE_SIZE, virtio::read_from_disk};
+use crate::{
+    cprint, cprintln,
+    errno::Errno,
+    mem::paging::Page,
+    param::PAGE_SIZE,
+    sleeplock::SleepLock,
+    virtio::{read_from_disk, write_to_disk},
+};
 use alloc::boxed::Box;
 use core::{
     ascii,
-    mem::{transmute, ManuallyDrop, MaybeUninit},
+    mem::{transmute, MaybeUninit},
 };
 pub use fs::*;
 use spin::Mutex;
 
+pub mod dir;
+pub mod fd;
+
 #[repr(transparent)]
 pub struct FileTable([FileMeta; MAX_FILES]);
 
@@ -14,109 +24,569 @@ pub static FILES: Mutex<FileTable> = Mutex::new(FileTable(
     [unsafe { core::mem::transmute(MaybeUninit::<FileMeta>::zeroed()) }; MAX_FILES],
 ));
 
+/// A file that's held on to between file operations, by an open file or as the working
+/// directory of a process. File ids are reused once a file is deleted, so the generation of
+/// the slot tells the file apart from the ones that get its id later.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FileRef {
+    pub file_id: FileId,
+    pub generation: u16,
+}
+
+impl FileRef {
+    pub fn of(file_meta: &FileMeta) -> Self {
+        FileRef {
+            file_id: file_meta.file_id,
+            generation: file_meta.generation,
+        }
+    }
+}
+
+/// The root directory, which is never deleted
+pub const ROOT_DIR: FileRef = FileRef {
+    file_id: ROOT_DIR_ID,
+    generation: 0,
+};
+
+/// Mount the disk: check its superblock, and load the free-node bitmap and the file table.
+/// Panics if the image was built for another layout, or its file table doesn't make sense.
 pub fn init_files() {
-    let mut files = FILES.lock();
     let mut buff = [0; 1024];
     read_from_disk(0, &mut buff).unwrap();
-    let file_buff: [FileMeta; const { 1024 / size_of::<FileMeta>() }] = unsafe { transmute(buff) };
-    (&mut files.0[0..(1024 / size_of::<FileMeta>())]).copy_from_slice(&file_buff);
-}
+    let superblock: Superblock = unsafe { (buff.as_ptr() as *const Superblock).read_unaligned() };
+    // `validate` rejects it too, but without the counts
+    if superblock.magic_number == SUPERBLOCK_MAGIC_NUMBER
+        && superblock.version == FS_VERSION
+        && superblock.max_files as usize > MAX_FILES
+    {
+        panic!(
+            "Can't mount the disk, it holds {} file slots but the kernel supports only {}",
+            superblock.max_files, MAX_FILES
+        );
+    }
+    if let Err(reason) = superblock.validate() {
+        panic!("Can't mount the disk, {}: {:?}", reason, superblock);
+    }
 
-impl FileTable {
-    pub fn ls(&self) {
-        cprintln!("FILE ID\t\tNAME\t\t\tSIZE");
-        for file_meta in self
-            .0
-            .iter()
-            .filter(|fm| fm.magic_number == FILE_MAGIC_NUMBER)
-        {
-            cprint!("{}\t\t", file_meta.file_id);
-            for chr in file_meta.name.iter().map(|c| {
-                if c.to_u8() == 0 {
-                    ascii::Char::Space
-                } else {
-                    *c
-                }
-            }) {
-                cprint!("{}", chr);
-            }
-            cprintln!("\t{}", file_meta.size);
+    let mut disk_fs = DISK_FS.lock();
+    let (bitmap_blocks, _) = disk_fs.bitmap.0.as_chunks_mut::<BLOCK_SIZE>();
+    for (i, block) in bitmap_blocks.iter_mut().enumerate() {
+        let sector = ((BITMAP_OFFSET + i * BLOCK_SIZE) / SECTOR_SIZE) as u64;
+        read_from_disk(sector, block).unwrap();
+    }
+    let mut files = FILES.lock();
+    let (table_blocks, _) = files.0.as_chunks_mut::<METAS_PER_BLOCK>();
+    for (i, block) in table_blocks.iter_mut().enumerate() {
+        let sector = ((FILE_TABLE_OFFSET + i * BLOCK_SIZE) / SECTOR_SIZE) as u64;
+        read_from_disk(sector, unsafe {
+            &mut *(block as *mut [FileMeta; METAS_PER_BLOCK] as *mut [u8; BLOCK_SIZE])
+        })
+        .unwrap();
+    }
+    let mut used = 0;
+    for (file_id, file_meta) in files.0.iter().enumerate() {
+        if file_meta.magic_number != FILE_MAGIC_NUMBER {
+            continue;
+        }
+        // Slot 0 is never used, and a file in the wrong slot would be found under another id
+        if file_id == 0 || file_meta.file_id as usize != file_id {
+            panic!(
+                "Can't mount the disk, file {} is stored in slot {}",
+                file_meta.file_id, file_id
+            );
         }
+        used += 1;
+    }
+    let root = &files.0[ROOT_DIR_ID as usize];
+    if root.magic_number != FILE_MAGIC_NUMBER || root.file_type != DIRECTORY {
+        panic!("Can't mount the disk, it has no root directory");
     }
+    cprintln!(
+        "Mounted the disk: {} of {} files in use",
+        used,
+        MAX_FILES - 1
+    );
+}
 
-    pub fn get_file_meta(&self, file_name: &str) -> Option<&FileMeta> {
-        let file_name_ascii = file_name.as_ascii()?;
-        self.0
-            .iter()
-            .find(|fm| strcmp_ascii(file_name_ascii, fm.name))
-    }
-
-    /// Copy the entire file data to ram, returning a slice of contigous Physical
-    /// Frames that contain the file data.
-    pub fn copy_to_ram(&self, file_name: &str) -> Option<ManuallyDrop<Box<[u8]>>> {
-        let file_meta = self.get_file_meta(file_name)?;
-        let pages = file_meta.size as usize / PAGE_SIZE + 2;
-        let file_frames: Box<[Page]> = unsafe { Box::new_zeroed_slice(pages).assume_init() };
-        let file_frames: Box<[[u8; PAGE_SIZE]]> = unsafe { transmute(file_frames) };
-        let mut file_data = file_frames.into_vec().into_flattened();
-        let head_node_id = file_meta.node_list_start;
-        let mut current_node_id = head_node_id;
-        for seg in (0..file_meta.size as usize)
-            .into_iter()
-            .step_by(FILE_DATA_SIZE)
-        {
-            let mut node: Node = unsafe { core::mem::transmute([0u8; 1024]) };
-            read_node(&mut node, current_node_id);
-            // #[cfg(debug_assertions)]
-            // cprintln!(
-            //     "Read Node {}. Next Node: {}",
-            //     current_node_id,
-            //     node.next_node
-            // );
-            file_data[seg..(seg + FILE_DATA_SIZE)].copy_from_slice(&node.data);
-            current_node_id = node.next_node;
+/// Print the files in the directory `path`
+pub fn ls(path: &str) {
+    let Ok(dir) = lookup(ROOT_DIR, path) else {
+        cprintln!("ls: {}: no such directory", path);
+        return;
+    };
+    cprintln!("FILE ID\t\tNAME\t\t\tSIZE");
+    let mut entries = [dir::EMPTY_DIR_ENTRY; 8];
+    let mut index = 0;
+    while let Ok(read @ 1..) = read_dir(FileRef::of(&dir), index, &mut entries) {
+        for entry in &entries[..read] {
+            let size = file_meta(entry.file_id).map_or(0, |fm| fm.size);
+            cprintln!("{}\t\t{:<24}{}", entry.file_id, entry.name(), size);
         }
-        core::hint::black_box(&mut file_data);
-        file_data.truncate(file_meta.size as usize);
-        #[cfg(debug_assertions)]
-        cprintln!(
-            "Done copying file {} to ram. The file is in {:#x}",
-            file_name,
-            file_data.as_ptr() as usize
+        index += read;
+    }
+}
+
+/// Copy the entire file data to ram, returning a slice of contigous Physical
+/// Frames that contain the file data. The data is freed once it's dropped.
+pub fn copy_to_ram(path: &str) -> Option<Box<[u8]>> {
+    copy_file_to_ram(FileRef::of(&lookup(ROOT_DIR, path).ok()?)).ok()
+}
+
+pub fn debug_file(path: &str) {
+    cprintln!("{:#?}", lookup(ROOT_DIR, path));
+}
+
+pub fn cat(path: &str) {
+    let file_data = copy_to_ram(path).unwrap();
+    for chr in &*file_data {
+        cprint!(
+            "{}",
+            ascii::Char::from_u8(*chr).unwrap_or(ascii::Char::QuestionMark)
         );
-        Some(ManuallyDrop::new(file_data.into_boxed_slice()))
     }
+}
 
-    pub fn debug_file(&self, file_name: &str) {
-        cprintln!("{:#?}", self.get_file_meta(file_name));
+/// Copy the data of the file to ram, see [`copy_to_ram`].
+/// Reading the disk might put the current process to sleep, so the file table doesn't need
+/// to stay locked while this runs.
+pub fn copy_file_to_ram(file: FileRef) -> Result<Box<[u8]>, Errno> {
+    let _disk_fs = DISK_FS.lock();
+    let file_meta = &file_meta_of(file)?;
+    let pages = file_meta.size as usize / PAGE_SIZE + 2;
+    let file_frames: Box<[Page]> = unsafe { Box::new_zeroed_slice(pages).assume_init() };
+    let file_frames: Box<[[u8; PAGE_SIZE]]> = unsafe { transmute(file_frames) };
+    let mut file_data = file_frames.into_vec().into_flattened();
+    let head_node_id = file_meta.node_list_start;
+    let mut current_node_id = head_node_id;
+    let mut node = empty_node();
+    for seg in (0..file_meta.size as usize)
+        .into_iter()
+        .step_by(FILE_DATA_SIZE)
+    {
+        read_node(&mut node, current_node_id)?;
+        file_data[seg..(seg + FILE_DATA_SIZE)].copy_from_slice(&node.data);
+        current_node_id = node.next_node;
     }
+    core::hint::black_box(&mut file_data);
+    file_data.truncate(file_meta.size as usize);
+    #[cfg(debug_assertions)]
+    cprintln!(
+        "Done copying file (id={}) to ram. The file is in {:#x}",
+        file_meta.file_id,
+        file_data.as_ptr() as usize
+    );
+    Ok(file_data.into_boxed_slice())
+}
 
-    pub fn cat(&self, file_name: &str) {
-        let file_data = self.copy_to_ram(file_name).unwrap();
-        for chr in &*file_data {
-            cprint!(
-                "{}",
-                ascii::Char::from_u8(*chr).unwrap_or(ascii::Char::QuestionMark)
-            );
+/// The current metadata of the file `file_id`, `ENOENT` if it was deleted
+pub fn file_meta(file_id: FileId) -> Result<FileMeta, Errno> {
+    FILES
+        .lock()
+        .0
+        .get(file_id as usize)
+        .filter(|fm| fm.magic_number == FILE_MAGIC_NUMBER)
+        .copied()
+        .ok_or(Errno::ENOENT)
+}
+
+/// The current metadata of the file, `ENOENT` if it was deleted, even if its id was given to
+/// another file since
+pub fn file_meta_of(file: FileRef) -> Result<FileMeta, Errno> {
+    file_meta(file.file_id).and_then(|fm| {
+        if fm.generation == file.generation {
+            Ok(fm)
+        } else {
+            Err(Errno::ENOENT)
         }
+    })
+}
+
+/// Find the file at `path`, relative to the directory `cwd` unless it starts with a `/`
+pub fn lookup(cwd: FileRef, path: &str) -> Result<FileMeta, Errno> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
     }
+    let _disk_fs = DISK_FS.lock();
+    dir::resolve(cwd, path)
 }
 
-fn read_node(buf: &mut Node, node_id: u32) {
-    let node_addr = node_address(node_id);
-    let node_sector = node_addr / SECTOR_SIZE;
-    read_from_disk(node_sector as u64, unsafe { transmute(&mut *buf) }).unwrap();
-    assert_eq!(buf.magic_number, NODE_MAGIC_NUMBER);
+/// Read the data of the file from `offset` into `buf`, walking the node list of the file from
+/// its start. Returns how many bytes were read, which is less than `buf.len()` only at the end
+/// of the file.
+pub fn read_file_at(file: FileRef, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+    let _disk_fs = DISK_FS.lock();
+    read_locked(&file_meta_of(file)?, offset, buf)
+}
+
+/// Read the entries of the directory into `entries`, starting at entry number `index`.
+/// Returns how many entries were read, 0 once there are no more.
+pub fn read_dir(dir: FileRef, index: usize, entries: &mut [DirEntry]) -> Result<usize, Errno> {
+    let _disk_fs = DISK_FS.lock();
+    let dir = file_meta_of(dir)?;
+    if dir.file_type != DIRECTORY {
+        return Err(Errno::ENOTDIR);
+    }
+    dir::read_entries(&dir, index, entries)
+}
+
+/// Create an empty file at `path`, see [`create`]
+pub fn create_file(cwd: FileRef, path: &str) -> Result<FileMeta, Errno> {
+    create(cwd, path, REGULAR_FILE)
+}
+
+/// Create an empty directory at `path`, see [`create`]
+pub fn make_dir(cwd: FileRef, path: &str) -> Result<FileMeta, Errno> {
+    create(cwd, path, DIRECTORY)
+}
+
+/// Delete the file at `path` and free all of its nodes, `EISDIR` if it's a directory. Files
+/// that are open keep their [`FileRef`], but any access through it returns `ENOENT`.
+pub fn delete_file(cwd: FileRef, path: &str) -> Result<(), Errno> {
+    remove(cwd, path, REGULAR_FILE)
+}
+
+/// Delete the directory at `path`, which must be empty (`ENOTEMPTY` otherwise)
+pub fn remove_dir(cwd: FileRef, path: &str) -> Result<(), Errno> {
+    remove(cwd, path, DIRECTORY)
+}
+
+/// Read from the file, see [`read_file_at`]
+fn read_locked(file_meta: &FileMeta, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+    let end = (offset.saturating_add(buf.len())).min(file_meta.size as usize);
+    if offset >= end {
+        return Ok(0);
+    }
+    let mut node = empty_node();
+    let mut current_node_id = nth_node(file_meta, offset / FILE_DATA_SIZE)?;
+    let mut pos = offset;
+    while pos < end {
+        read_node(&mut node, current_node_id)?;
+        let node_offset = pos % FILE_DATA_SIZE;
+        let chunk = (FILE_DATA_SIZE - node_offset).min(end - pos);
+        buf[(pos - offset)..(pos - offset + chunk)]
+            .copy_from_slice(&node.data[node_offset..(node_offset + chunk)]);
+        pos += chunk;
+        current_node_id = node.next_node;
+    }
+    Ok(end - offset)
+}
+
+/// Write `data` to the file at `offset`, and grow the file if it ends up longer. If `offset`
+/// is past the end of the file, the gap reads as zeros. Returns how many bytes were written.
+pub fn write_file_at(file: FileRef, offset: usize, data: &[u8]) -> Result<usize, Errno> {
+    let mut disk_fs = DISK_FS.lock();
+    file_meta_of(file)?;
+    write_locked(&mut disk_fs, file.file_id, offset, data)
+}
+
+/// Write `data` at the end of the file. Returns the new size of the file.
+pub fn append_file(file: FileRef, data: &[u8]) -> Result<usize, Errno> {
+    let mut disk_fs = DISK_FS.lock();
+    let offset = file_meta_of(file)?.size as usize;
+    write_locked(&mut disk_fs, file.file_id, offset, data)?;
+    Ok(offset + data.len())
 }
 
-fn strcmp_ascii<const N: usize>(s: &[ascii::Char], ass: [ascii::Char; N]) -> bool {
-    if N < s.len() {
-        return false;
+/// Change the size of the file to `size`, freeing the nodes it no longer needs. If the file
+/// grows, the new part reads as zeros.
+pub fn truncate_file(file: FileRef, size: usize) -> Result<(), Errno> {
+    let mut disk_fs = DISK_FS.lock();
+    file_meta_of(file)?;
+    truncate_locked(&mut disk_fs, file.file_id, size)
+}
+
+/// Create an empty file of type `file_type` at `path`, relative to `cwd`, and return its
+/// metadata. Returns `EEXIST` if there already is a file there, and `ENOSPC` if there is no
+/// room for another file.
+fn create(cwd: FileRef, path: &str, file_type: u16) -> Result<FileMeta, Errno> {
+    let mut disk_fs = DISK_FS.lock();
+    let (dir, name) = dir::resolve_parent(cwd, path)?;
+    let mut entry = dir::new_entry(name)?;
+    if dir::find_entry(&dir, name)?.is_some() {
+        return Err(Errno::EEXIST);
+    }
+    // File id 0 is never used
+    let (file_id, generation) = {
+        let files = FILES.lock();
+        let file_id = files.0[1..]
+            .iter()
+            .position(|fm| fm.magic_number != FILE_MAGIC_NUMBER)
+            .ok_or(Errno::ENOSPC)?
+            + 1;
+        // Whoever still holds the file that used the slot last won't find this one
+        (
+            file_id as FileId,
+            files.0[file_id].generation.wrapping_add(1),
+        )
+    };
+    let node_id = disk_fs.alloc()?;
+    write_node(&new_node(file_id, node_id, node_id), node_id)?;
+    let file_meta = FileMeta {
+        magic_number: FILE_MAGIC_NUMBER,
+        node_list_start: node_id,
+        file_id,
+        file_type,
+        size: 0,
+        parent: dir.file_id,
+        generation,
+        reserved: [0; 12],
+    };
+    write_meta(file_id, &file_meta)?;
+    entry.file_id = file_id;
+    if let Err(errno) = dir::add_entry(&mut disk_fs, &dir, &entry) {
+        disk_fs.free(node_id)?;
+        write_meta(file_id, &free_file_meta(generation))?;
+        return Err(errno);
     }
-    for i in 0..N {
-        if *s.get(i).unwrap_or(&ascii::Char::from_u8(0).unwrap()) != ass[i] {
-            return false;
+    Ok(file_meta)
+}
+
+/// Delete the file of type `file_type` at `path`, relative to `cwd`
+fn remove(cwd: FileRef, path: &str, file_type: u16) -> Result<(), Errno> {
+    let mut disk_fs = DISK_FS.lock();
+    let (dir, name) = dir::resolve_parent(cwd, path)?;
+    let (index, entry) = dir::find_entry(&dir, name)?.ok_or(Errno::ENOENT)?;
+    let file_meta = file_meta(entry.file_id)?;
+    match (file_type, file_meta.file_type) {
+        (REGULAR_FILE, DIRECTORY) => return Err(Errno::EISDIR),
+        (DIRECTORY, REGULAR_FILE) => return Err(Errno::ENOTDIR),
+        (DIRECTORY, _) if file_meta.size != 0 => return Err(Errno::ENOTEMPTY),
+        _ => {}
+    }
+    dir::remove_entry(&mut disk_fs, &dir, index)?;
+    let first = file_meta.node_list_start;
+    cut_nodes(&mut disk_fs, first, 1)?;
+    disk_fs.free(first)?;
+    write_meta(file_meta.file_id, &free_file_meta(file_meta.generation))
+}
+
+/// Serializes the file operations that go to the disk, so none of them sees a file that's
+/// halfway through a change. Lock it before [`FILES`].
+static DISK_FS: SleepLock<NodeAllocator> = SleepLock::new(NodeAllocator {
+    bitmap: NodeBitmap::new(),
+});
+
+/// The file table is read from the disk a block at a time
+const METAS_PER_BLOCK: usize = BLOCK_SIZE / size_of::<FileMeta>();
+
+const EMPTY_FILE_META: FileMeta = unsafe { MaybeUninit::zeroed().assume_init() };
+
+/// The metadata of a free slot, which remembers the generation of the file that was in it
+fn free_file_meta(generation: u16) -> FileMeta {
+    FileMeta {
+        generation,
+        ..EMPTY_FILE_META
+    }
+}
+
+/// Hands out the nodes of the disk, keeping a copy of the free-node bitmap that's loaded at boot
+struct NodeAllocator {
+    bitmap: NodeBitmap,
+}
+
+impl NodeAllocator {
+    /// Take a free node, and mark it as taken on the disk
+    fn alloc(&mut self) -> Result<NodeId, Errno> {
+        let node_id = self.bitmap.alloc().ok_or(Errno::ENOSPC)?;
+        if let Err(errno) = self.write_bitmap(node_id) {
+            self.bitmap.free(node_id);
+            return Err(errno);
         }
+        Ok(node_id)
+    }
+
+    /// Mark the node as free on the disk
+    fn free(&mut self, node_id: NodeId) -> Result<(), Errno> {
+        let mut node = empty_node();
+        read_node(&mut node, node_id)?;
+        node.flags &= !NODE_USED;
+        write_node(&node, node_id)?;
+        self.bitmap.free(node_id);
+        self.write_bitmap(node_id)
     }
-    true
+
+    /// Write the block of the bitmap that holds the bit of `node_id` back to the disk
+    fn write_bitmap(&self, node_id: NodeId) -> Result<(), Errno> {
+        let (addr, block) = self.bitmap.block_of(node_id);
+        write_to_disk((addr / SECTOR_SIZE) as u64, block).map_err(|_| Errno::EIO)
+    }
+}
+
+/// Write `data` to the file, see [`write_file_at`]
+fn write_locked(
+    disk_fs: &mut NodeAllocator,
+    file_id: FileId,
+    offset: usize,
+    data: &[u8],
+) -> Result<usize, Errno> {
+    let mut file_meta = file_meta(file_id)?;
+    if data.is_empty() {
+        return Ok(0);
+    }
+    let end = offset.checked_add(data.len()).ok_or(Errno::EFBIG)?;
+    let grows = end > file_meta.size as usize;
+    if grows {
+        resize(disk_fs, &mut file_meta, end)?;
+    }
+    let mut node = empty_node();
+    let mut current_node_id = nth_node(&file_meta, offset / FILE_DATA_SIZE)?;
+    let mut pos = offset;
+    while pos < end {
+        read_node(&mut node, current_node_id)?;
+        let node_offset = pos % FILE_DATA_SIZE;
+        let chunk = (FILE_DATA_SIZE - node_offset).min(end - pos);
+        node.data[node_offset..(node_offset + chunk)]
+            .copy_from_slice(&data[(pos - offset)..(pos - offset + chunk)]);
+        write_node(&node, current_node_id)?;
+        pos += chunk;
+        current_node_id = node.next_node;
+    }
+    if grows {
+        write_meta(file_id, &file_meta)?;
+    }
+    Ok(data.len())
+}
+
+/// Change the size of the file, see [`truncate_file`]
+fn truncate_locked(disk_fs: &mut NodeAllocator, file_id: FileId, size: usize) -> Result<(), Errno> {
+    let mut file_meta = file_meta(file_id)?;
+    resize(disk_fs, &mut file_meta, size)?;
+    write_meta(file_id, &file_meta)
+}
+
+/// Grow or shrink the node list of the file to fit `size` bytes, and set its size. The new
+/// part of a file that grows reads as zeros. Only the copy of the metadata in `file_meta` is
+/// changed.
+fn resize(disk_fs: &mut NodeAllocator, file_meta: &mut FileMeta, size: usize) -> Result<(), Errno> {
+    if size > u32::MAX as usize {
+        return Err(Errno::EFBIG);
+    }
+    let old_size = file_meta.size as usize;
+    let first = file_meta.node_list_start;
+    let old_nodes = nodes_for_size(old_size);
+    let new_nodes = nodes_for_size(size);
+    if size > old_size {
+        // A truncate leaves its garbage after the end of the file in the last node
+        let mut node = empty_node();
+        read_node(&mut node, first)?;
+        let last = node.prev_node;
+        read_node(&mut node, last)?;
+        node.data[old_size % FILE_DATA_SIZE..].fill(0);
+        write_node(&node, last)?;
+        for _ in old_nodes..new_nodes {
+            if let Err(errno) = append_node(disk_fs, file_meta.file_id, first) {
+                cut_nodes(disk_fs, first, old_nodes)?;
+                return Err(errno);
+            }
+        }
+    } else if new_nodes < old_nodes {
+        cut_nodes(disk_fs, first, new_nodes)?;
+    }
+    file_meta.size = size as u32;
+    Ok(())
+}
+
+/// Add a zeroed node to the end of the node list that starts at `first`
+fn append_node(disk_fs: &mut NodeAllocator, file_id: FileId, first: NodeId) -> Result<(), Errno> {
+    let node_id = disk_fs.alloc()?;
+    let mut node = empty_node();
+    read_node(&mut node, first)?;
+    let last = node.prev_node;
+    write_node(&new_node(file_id, first, last), node_id)?;
+    read_node(&mut node, last)?;
+    node.next_node = node_id;
+    write_node(&node, last)?;
+    read_node(&mut node, first)?;
+    node.prev_node = node_id;
+    write_node(&node, first)
+}
+
+/// Free every node of the node list that starts at `first`, except the first `keep` (at least
+/// one).
+fn cut_nodes(disk_fs: &mut NodeAllocator, first: NodeId, keep: usize) -> Result<(), Errno> {
+    let mut node = empty_node();
+    let mut last = first;
+    for _ in 1..keep {
+        read_node(&mut node, last)?;
+        last = node.next_node;
+    }
+    read_node(&mut node, last)?;
+    let mut current_node_id = node.next_node;
+    if current_node_id == first {
+        return Ok(());
+    }
+    node.next_node = first;
+    write_node(&node, last)?;
+    read_node(&mut node, first)?;
+    node.prev_node = last;
+    write_node(&node, first)?;
+    while current_node_id != first {
+        read_node(&mut node, current_node_id)?;
+        let next_node_id = node.next_node;
+        disk_fs.free(current_node_id)?;
+        current_node_id = next_node_id;
+    }
+    Ok(())
+}
+
+/// The id of the node that holds the data at `index * FILE_DATA_SIZE` in the file
+fn nth_node(file_meta: &FileMeta, index: usize) -> Result<NodeId, Errno> {
+    let mut node = empty_node();
+    let mut current_node_id = file_meta.node_list_start;
+    for _ in 0..index {
+        read_node(&mut node, current_node_id)?;
+        current_node_id = node.next_node;
+    }
+    Ok(current_node_id)
+}
+
+/// Write the metadata of the file `file_id` to the disk and to the file table
+fn write_meta(file_id: FileId, file_meta: &FileMeta) -> Result<(), Errno> {
+    let meta_addr = file_meta_address(file_id);
+    let block_addr = meta_addr - meta_addr % BLOCK_SIZE;
+    let sector = (block_addr / SECTOR_SIZE) as u64;
+    let mut block = [0u8; BLOCK_SIZE];
+    read_from_disk(sector, &mut block).map_err(|_| Errno::EIO)?;
+    let meta_bytes: &[u8; size_of::<FileMeta>()] = unsafe { transmute(file_meta) };
+    block[(meta_addr - block_addr)..(meta_addr - block_addr + size_of::<FileMeta>())]
+        .copy_from_slice(meta_bytes);
+    write_to_disk(sector, &block).map_err(|_| Errno::EIO)?;
+    FILES.lock().0[file_id as usize] = *file_meta;
+    Ok(())
+}
+
+/// A used node with no data
+fn new_node(file_id: FileId, next_node: NodeId, prev_node: NodeId) -> Node {
+    Node {
+        magic_number: NODE_MAGIC_NUMBER,
+        file_id,
+        flags: NODE_USED,
+        next_node,
+        prev_node,
+        data: [0; FILE_DATA_SIZE],
+    }
+}
+
+fn empty_node() -> Node {
+    unsafe { transmute([0u8; NODE_SIZE]) }
+}
+
+fn node_sector(node_id: NodeId) -> u64 {
+    (node_address(node_id) / SECTOR_SIZE) as u64
+}
+
+fn read_node(buf: &mut Node, node_id: NodeId) -> Result<(), Errno> {
+    read_from_disk(node_sector(node_id), unsafe {
+        transmute::<&mut Node, &mut [u8; NODE_SIZE]>(buf)
+    })
+    .map_err(|_| Errno::EIO)?;
+    assert_eq!(buf.magic_number, NODE_MAGIC_NUMBER);
+    Ok(())
+}
+
+fn write_node(node: &Node, node_id: NodeId) -> Result<(), Errno> {
+    write_to_disk(node_sector(node_id), unsafe {
+        transmute::<&Node, &[u8; NODE_SIZE]>(node)
+    })
+    .map_err(|_| Errno::EIO)
 }
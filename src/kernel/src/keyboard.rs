@@ -1,4 +1,4 @@
-use crate::cprintln;
+use crate::{cprintln, cpu::cproc};
 use spin::Mutex;
 
 type KeyBoardPtr = u16;
@@ -12,8 +12,12 @@ pub struct Keyboard {
     w_pointer: KeyBoardPtr,
 }
 
+/// The keys the UART received and nobody read yet
 pub static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard::new());
 
+/// What terminals send for the backspace key
+pub const BACKSPACE: Key = 0x7f;
+
 impl Keyboard {
     const fn new() -> Self {
         Keyboard {
@@ -35,7 +39,7 @@ impl Keyboard {
 
     pub fn update_new_press(&mut self, key: Key) -> Result<(), ()> {
         if let Some(pending_key) = self.pending.take() {
-            return self.update_new_press(pending_key);
+            self.update_new_press(pending_key)?;
         }
         if self.w_pointer.wrapping_add(1) == self.r_pointer {
             // Buffer full
@@ -49,6 +53,32 @@ impl Keyboard {
     }
 }
 
+/// What processes that wait for input sleep on
+pub fn input_chan() -> usize {
+    &KEYBOARD as *const _ as usize
+}
+
+/// Fill `buf` with the keys that were pressed, up to and including the first newline. Blocks the
+/// current process until at least one key was pressed. Returns how many keys were read.
+pub fn read_keys(buf: &mut [Key]) -> usize {
+    let mut keyboard = KEYBOARD.lock();
+    let mut read = 0;
+    while read < buf.len() {
+        match keyboard.read_next_press() {
+            Some(key) => {
+                buf[read] = key;
+                read += 1;
+                if key == b'\n' {
+                    break;
+                }
+            }
+            None if read == 0 => keyboard = cproc().sleep(input_chan(), &KEYBOARD, keyboard),
+            None => break,
+        }
+    }
+    read
+}
+
 pub fn read_recent_input() {
     let mut keyboard = KEYBOARD.lock();
     while let Some(key) = keyboard.read_next_press() {
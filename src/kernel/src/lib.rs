@@ -5,43 +5,11 @@
 #![feature(fn_align)]
 #![feature(panic_info_message)]
 #![feature(ascii_char)]
-#![feature(str_from_raw_parts)]
 #![feature(ascii_char_variants)]
 #![feature(riscv_ext_intrinsics)]
 #![no_std]
 #![no_main]
 
-#[panic_handler]
-fn panic(info: &PanicInfo) -> ! {
-    use core::hint;
-    // unsafe {
-    //     UART.force_unlock();
-    //     CONSOLE.force_unlock();
-    //     let mut uart = UART.lock();
-
-    //     uart.write_chars(b"\nPANIC: ");
-    //     if let Some(msg) = info.message().as_str() {
-    //         uart.write_chars(msg.as_bytes());
-    //     } else {
-    //         uart.write_chars(b"X");
-    //     }
-    //     uart.write_chars(b"\nFILE: ");
-    //     uart.write_chars(info.location().unwrap().file().as_bytes());
-    //     uart.write_chars(b"\nLINE: ");
-    //     let mut line = info.location().unwrap().line();
-    //     while line != 0 {
-    //         uart.put_char((line % 10) as u8 + 48);
-    //         line /= 10;
-    //     }
-    //     uart.put_char(b'\n');
-    // };
-
-    cprintln!("Encountered Panic (tp={}): {:#}", tp::read(), info);
-    loop {
-        hint::spin_loop();
-    }
-}
-
 extern crate alloc;
 
 pub mod arch;
@@ -49,6 +17,7 @@ pub mod console;
 pub mod cpu;
 pub mod elf_parse;
 pub mod entry;
+pub mod errno;
 pub mod files;
 pub mod kernelvec;
 pub mod keyboard;
@@ -58,16 +27,17 @@ pub mod param;
 pub mod plic;
 pub mod proc;
 pub mod scheduler;
+pub mod sleeplock;
 pub mod start;
+pub mod swtch;
 pub mod syscall;
+pub mod time;
 pub mod trampoline;
 pub mod trap;
 pub mod uart;
 pub mod virtio;
 
-use arch::registers::tp;
 pub use console::*;
-use core::panic::PanicInfo;
 
 extern "C" {
     fn end();
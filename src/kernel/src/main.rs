@@ -6,10 +6,10 @@
 
 extern crate alloc;
 
-use crate::files::FILES;
 use arch::asm::wfi;
 use arch::interrupts::s_disable;
-use arch::registers::stvec;
+use arch::registers::{self, stvec};
+use core::panic::PanicInfo;
 use core::ptr::addr_of;
 use core::sync::atomic::AtomicBool;
 use core::sync::atomic::*;
@@ -21,8 +21,49 @@ use kernel::{cprintln, end_of_kernel_code_section, end_of_kernel_data_section};
 use proc::{cpuid, proc, procs};
 use scheduler::scheduler;
 
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    use core::hint;
+    // unsafe {
+    //     UART.force_unlock();
+    //     CONSOLE.force_unlock();
+    //     let mut uart = UART.lock();
+
+    //     uart.write_chars(b"\nPANIC: ");
+    //     if let Some(msg) = info.message().as_str() {
+    //         uart.write_chars(msg.as_bytes());
+    //     } else {
+    //         uart.write_chars(b"X");
+    //     }
+    //     uart.write_chars(b"\nFILE: ");
+    //     uart.write_chars(info.location().unwrap().file().as_bytes());
+    //     uart.write_chars(b"\nLINE: ");
+    //     let mut line = info.location().unwrap().line();
+    //     while line != 0 {
+    //         uart.put_char((line % 10) as u8 + 48);
+    //         line /= 10;
+    //     }
+    //     uart.put_char(b'\n');
+    // };
+
+    cprintln!(
+        "Encountered Panic (tp={}): {:#}",
+        registers::tp::read(),
+        info
+    );
+    loop {
+        hint::spin_loop();
+    }
+}
+
+#[global_allocator]
+static GLOBAL_ALLOCATOR: mem::KernelAllocator = mem::KernelAllocator;
+
 static STARTED: AtomicBool = AtomicBool::new(false);
 
+/// The program the kernel starts once it has booted
+const FIRST_USER_PROGRAM: &str = "sh";
+
 #[export_name = "main"]
 extern "C" fn main() -> ! {
     let hart_id = cpuid();
@@ -69,15 +110,9 @@ unsafe fn init_kernel() {
     virtio::init_virtio();
     files::init_files();
 
-    let data = FILES.lock().copy_to_ram("print").unwrap();
+    let data = files::copy_to_ram(FIRST_USER_PROGRAM)
+        .expect("The first user program is missing from the disk");
     let exe = parse_executable_file(&data).unwrap();
-    for _ in 0..30 {
-        let pid = procs().alloc_proc("print").unwrap();
-        proc(pid).activate(&exe);
-    }
-
-    // let data1 = FILES.lock().copy_to_ram("print").unwrap();
-    // let pid1 = procs().alloc_proc("print1").unwrap();
-    // let exe1 = parse_executable_file(&data1).unwrap();
-    // proc(pid1).activate(exe1);
+    let pid = procs().alloc_proc(FIRST_USER_PROGRAM).unwrap();
+    proc(pid).activate(&exe);
 }
@@ -0,0 +1,145 @@
+use super::{
+    alloc_frame,
+    paging::{PageTable, PageTableLevel},
+    virtual_mem::{PTEFlags, PhysAddr, VirtAddr},
+};
+use crate::{
+    errno::Errno,
+    param::{MMAP_END, MMAP_START, PAGE_SIZE},
+};
+
+/// `mmap` and `mprotect` protections, any combination of them (or [`PROT_NONE`])
+pub const PROT_NONE: usize = 0;
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+
+/// `mmap` flags, only anonymous (zero filled) mappings are supported
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+/// The flags of a user page with the protection `prot`. A leaf entry must be readable or
+/// executable, so writable pages are readable as well, and [`PROT_NONE`] pages are readable
+/// but hidden from user mode.
+fn prot_flags(prot: usize) -> Result<PTEFlags, Errno> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut flags = PTEFlags::valid().owned();
+    if prot == PROT_NONE {
+        return Ok(flags.readable());
+    }
+    flags = flags.userable();
+    if prot & (PROT_READ | PROT_WRITE) != 0 {
+        flags = flags.readable();
+    }
+    if prot & PROT_WRITE != 0 {
+        flags = flags.writable();
+    }
+    if prot & PROT_EXEC != 0 {
+        flags = flags.executable();
+    }
+    Ok(flags)
+}
+
+/// The pages of `addr..addr + len`. Returns `EINVAL` if `addr` isn't page aligned or the range
+/// is empty.
+fn pages(addr: usize, len: usize) -> Result<impl Iterator<Item = VirtAddr>, Errno> {
+    if !addr.is_multiple_of(PAGE_SIZE) || len == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let end = addr
+        .checked_add(len.next_multiple_of(PAGE_SIZE))
+        .ok_or(Errno::EINVAL)?;
+    Ok((addr..end)
+        .step_by(PAGE_SIZE)
+        .map(|page| VirtAddr::from_raw(page as u64)))
+}
+
+/// Find `len` bytes worth of unmapped pages in the mmap region, starting the search at `hint`
+/// if it's in the region.
+fn find_free_range(pt: &PageTable, hint: usize, len: usize) -> Option<usize> {
+    let len = len.next_multiple_of(PAGE_SIZE);
+    let mut start = if (MMAP_START..MMAP_END).contains(&hint) {
+        hint & !(PAGE_SIZE - 1)
+    } else {
+        MMAP_START
+    };
+    'search: while start + len <= MMAP_END {
+        for page in (start..start + len).step_by(PAGE_SIZE) {
+            if pt.entry(VirtAddr::from_raw(page as u64)).is_some() {
+                start = page + PAGE_SIZE;
+                continue 'search;
+            }
+        }
+        return Some(start);
+    }
+    None
+}
+
+/// Map `len` bytes (rounded up to whole pages) of new zeroed memory with the protection `prot`
+/// into the page table, near `hint` if there is room there. `flags` must be [`MAP_ANONYMOUS`].
+/// Returns the address of the new memory, or `ENOMEM` if there isn't enough memory or room.
+pub fn mmap(
+    pt: &mut PageTable,
+    hint: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+) -> Result<usize, Errno> {
+    if flags != MAP_ANONYMOUS || len == 0 || len > MMAP_END - MMAP_START {
+        return Err(Errno::EINVAL);
+    }
+    let pte_flags = prot_flags(prot)?;
+    let addr = find_free_range(pt, hint, len).ok_or(Errno::ENOMEM)?;
+    for page in pages(addr, len)? {
+        let Some(frame) = (unsafe { alloc_frame() }) else {
+            munmap(pt, addr, page.as_u64() as usize - addr)?;
+            return Err(Errno::ENOMEM);
+        };
+        pt.strong_map(
+            page,
+            PhysAddr::from_raw(frame.as_ptr() as u64),
+            pte_flags,
+            PageTableLevel::L2,
+        );
+    }
+    Ok(addr)
+}
+
+/// Unmap the pages of `addr..addr + len` and free their frames. Pages that aren't mapped are
+/// skipped. Only the mmap region can be unmapped, anything else returns `EINVAL`.
+pub fn munmap(pt: &mut PageTable, addr: usize, len: usize) -> Result<(), Errno> {
+    if addr < MMAP_START || addr.saturating_add(len) > MMAP_END {
+        return Err(Errno::EINVAL);
+    }
+    if len == 0 {
+        return Ok(());
+    }
+    for page in pages(addr, len)? {
+        pt.unmap(page);
+    }
+    Ok(())
+}
+
+/// Change the protection of the pages of `addr..addr + len` to `prot`. Returns `ENOMEM` if any
+/// of them isn't a mapped user page, in which case none of them are changed.
+pub fn mprotect(pt: &mut PageTable, addr: usize, len: usize, prot: usize) -> Result<(), Errno> {
+    let flags = prot_flags(prot)?;
+    // Check every page before changing any
+    for page in pages(addr, len)? {
+        match pt.entry(page) {
+            Some(pte) if pte.is_owned() => {}
+            _ => return Err(Errno::ENOMEM),
+        }
+    }
+    for page in pages(addr, len)? {
+        let pte = pt.entry(page).expect("The page was just checked");
+        pt.strong_map(
+            page,
+            PhysAddr::from_raw(pte.frame_addr()),
+            flags,
+            PageTableLevel::L2,
+        );
+    }
+    Ok(())
+}
@@ -1,19 +1,36 @@
+pub mod mmap;
 pub mod paging;
+pub mod uaccess;
 pub mod virtual_mem;
 
 use crate::{end_of_kernel_data_section, param::RAM_SIZE};
-use core::{alloc::Layout, ptr::NonNull};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::NonNull,
+};
 use linked_list_allocator::LockedHeap;
 use paging::{zerod_frame, Frame};
 
 #[cfg(not(feature = "debug-allocations"))]
-#[global_allocator]
 pub static mut ALLOCATOR: LockedHeap = LockedHeap::empty();
 
 #[cfg(feature = "debug-allocations")]
-#[global_allocator]
 pub static mut ALLOCATOR: DebugAllocator = DebugAllocator(LockedHeap::empty());
 
+/// Hands the allocations of the kernel to [`ALLOCATOR`]. The kernel binary registers it as the
+/// `#[global_allocator]`, the library can't, because user programs link it and bring their own.
+pub struct KernelAllocator;
+
+unsafe impl GlobalAlloc for KernelAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATOR.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATOR.dealloc(ptr, layout)
+    }
+}
+
 pub unsafe fn init_kernel_allocator() {
     ALLOCATOR
         .lock()
@@ -58,8 +75,18 @@ pub unsafe fn alloc_frame() -> Option<NonNull<Frame>> {
         })
 }
 
+/// Give back a frame that was allocated with [`alloc_frame`] or [`alloc_frame_unwrap`]
+///
+/// # Safety
+/// The frame must not be freed already, and nothing may use it after this.
+pub unsafe fn free_frame(frame: NonNull<Frame>) {
+    ALLOCATOR
+        .lock()
+        .deallocate(frame.cast(), Layout::new::<Frame>());
+}
+
 #[cfg(feature = "debug-allocations")]
-unsafe impl core::alloc::GlobalAlloc for DebugAllocator {
+unsafe impl GlobalAlloc for DebugAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let p = self.0.alloc(layout);
         cprintln!("Allocated: {:#?}", layout);
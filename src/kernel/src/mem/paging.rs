@@ -1,6 +1,6 @@
 use riscv::{asm::sfence_vma_all, register::satp};
 
-use super::{alloc_frame_unwrap, virtual_mem::*};
+use super::{alloc_frame_unwrap, free_frame, virtual_mem::*};
 use crate::{
     cprint, cprintln, end_of_kernel_code_section, end_of_kernel_data_section,
     memlayout::{
@@ -10,6 +10,7 @@ use crate::{
     param::{PAGE_SIZE, RAM_SIZE},
     trampoline::trampoline,
 };
+use core::ptr::NonNull;
 
 /// The kernel L3 page table
 pub static mut KERNEL_PAGE_TABLE: PageTable = PageTable::zeroed();
@@ -260,6 +261,107 @@ impl PageTable {
         }
     }
 
+    /// Find the leaf entry that maps `va`, if there is one.
+    pub fn entry(&self, va: VirtAddr) -> Option<PageTableEntry> {
+        let mut table = self;
+        let mut level = PageTableLevel::L2;
+        loop {
+            let pte = table.0[va.vpn(level) as usize];
+            if !pte.is_valid() {
+                return None;
+            }
+            match level.one_level_down() {
+                Some(level_down) if pte.is_redirect() => {
+                    table = unsafe { &*(pte.frame_addr() as *const PageTable) };
+                    level = level_down;
+                }
+                _ => return (!pte.is_redirect()).then_some(pte),
+            }
+        }
+    }
+
+    /// Unmap the page that contains `va`, and free its frame if it's owned by the table.
+    /// Returns the entry that mapped it, if there was one. The tables below stay allocated.
+    pub fn unmap(&mut self, va: VirtAddr) -> Option<PageTableEntry> {
+        let mut table = self;
+        let mut level = PageTableLevel::L2;
+        loop {
+            let pte = &mut table.0[va.vpn(level) as usize];
+            if !pte.is_valid() {
+                return None;
+            }
+            match level.one_level_down() {
+                Some(level_down) if pte.is_redirect() => {
+                    table = unsafe { &mut *(pte.frame_addr() as *mut PageTable) };
+                    level = level_down;
+                }
+                _ if pte.is_redirect() => return None,
+                _ => {
+                    let prev_pte = *pte;
+                    *pte = PageTableEntry::new_invalid();
+                    if prev_pte.is_owned() {
+                        unsafe {
+                            free_frame(NonNull::new_unchecked(prev_pte.frame_addr() as *mut _))
+                        };
+                    }
+                    return Some(prev_pte);
+                }
+            }
+        }
+    }
+
+    /// Unmap everything in the table. The frames of the tables below it are freed, and so is
+    /// every frame that is owned by the table (mapped with [`PTEFlags::owned`]).
+    /// The table itself stays allocated (and empty).
+    pub fn clear(&mut self) {
+        self.clear_from(PageTableLevel::L2);
+    }
+
+    fn clear_from(&mut self, level: PageTableLevel) {
+        for pte in self.0.iter_mut().filter(|e| e.is_valid()) {
+            match level.one_level_down() {
+                Some(level_down) if pte.is_redirect() => {
+                    let table = pte.frame_addr() as *mut PageTable;
+                    unsafe {
+                        (*table).clear_from(level_down);
+                        free_frame(NonNull::new_unchecked(table.cast()));
+                    }
+                }
+                _ => {
+                    if pte.is_owned() {
+                        unsafe { free_frame(NonNull::new_unchecked(pte.frame_addr() as *mut _)) };
+                    }
+                }
+            }
+            *pte = PageTableEntry::new_invalid();
+        }
+    }
+
+    /// Call `f` with every valid leaf entry (an entry that points to a frame) in the table,
+    /// along with the virtual address it maps.
+    pub fn for_each_leaf(&self, f: &mut impl FnMut(VirtAddr, PageTableEntry)) {
+        self.for_each_leaf_from(PageTableLevel::L2, 0, f);
+    }
+
+    fn for_each_leaf_from(
+        &self,
+        level: PageTableLevel,
+        va_base: u64,
+        f: &mut impl FnMut(VirtAddr, PageTableEntry),
+    ) {
+        for (idx, pte) in self.0.iter().enumerate().filter(|(_, e)| e.is_valid()) {
+            let va = va_base | ((idx as u64) << (12 + 9 * level as u64));
+            if pte.is_redirect() {
+                if let Some(level_down) = level.one_level_down() {
+                    unsafe { &*(pte.frame_addr() as *const PageTable) }
+                        .for_each_leaf_from(level_down, va, f);
+                }
+            } else {
+                f(VirtAddr::from_raw(va), *pte);
+            }
+        }
+    }
+
     pub fn debug(&self, prefix: &str, level: usize) {
         macro_rules! prefix_print {
             ($($arg:tt)*) =>{
@@ -0,0 +1,88 @@
+use super::{
+    paging::PageTable,
+    virtual_mem::{PTEFlags, VirtAddr},
+};
+use crate::{errno::Errno, param::PAGE_SIZE};
+use alloc::{string::String, vec::Vec};
+
+/// User addresses must be below this, the upper half of Sv39 belongs to the kernel
+const MAX_USER_VA: usize = 1 << 38;
+
+/// Call `f` with the physical address and the length of each piece of the user range
+/// `va..va + len` in the page table, one piece per page. Every page is checked to be mapped for
+/// user mode with `flags` before `f` is called on it. Returns `EFAULT` at the first page that
+/// isn't, the pieces before it were already handed to `f`.
+/// User memory is mapped with 4KB pages only.
+fn for_each_user_page(
+    pt: &PageTable,
+    va: usize,
+    len: usize,
+    flags: PTEFlags,
+    mut f: impl FnMut(*mut u8, usize),
+) -> Result<(), Errno> {
+    let end = va.checked_add(len).ok_or(Errno::EFAULT)?;
+    if end > MAX_USER_VA {
+        return Err(Errno::EFAULT);
+    }
+    let mut current = va;
+    while current < end {
+        let va = VirtAddr::from_raw(current as u64);
+        let pte = pt.entry(va).ok_or(Errno::EFAULT)?;
+        let allowed = pte.is_userable()
+            && (!flags.is_readable() || pte.is_readable())
+            && (!flags.is_writable() || pte.is_writable());
+        if !allowed {
+            return Err(Errno::EFAULT);
+        }
+        let chunk = (PAGE_SIZE - va.offset() as usize).min(end - current);
+        f((pte.frame_addr() | va.offset()) as *mut u8, chunk);
+        current += chunk;
+    }
+    Ok(())
+}
+
+/// Fill `dst` with the bytes at the user address `src`, which must be readable by the user.
+pub fn copy_from_user(pt: &PageTable, dst: &mut [u8], src: usize) -> Result<(), Errno> {
+    let mut copied = 0;
+    for_each_user_page(
+        pt,
+        src,
+        dst.len(),
+        PTEFlags::valid().readable(),
+        |pa, chunk| {
+            unsafe { pa.copy_to_nonoverlapping(dst[copied..].as_mut_ptr(), chunk) };
+            copied += chunk;
+        },
+    )
+}
+
+/// Copy `src` to the user address `dst`, which must be writable by the user.
+pub fn copy_to_user(pt: &PageTable, dst: usize, src: &[u8]) -> Result<(), Errno> {
+    let mut copied = 0;
+    for_each_user_page(
+        pt,
+        dst,
+        src.len(),
+        PTEFlags::valid().writable(),
+        |pa, chunk| {
+            unsafe { pa.copy_from_nonoverlapping(src[copied..].as_ptr(), chunk) };
+            copied += chunk;
+        },
+    )
+}
+
+/// Copy the `len` bytes at the user address `src` into a kernel buffer. The buffer only grows
+/// with the pages that were checked, so a bogus `len` can't make the kernel allocate much.
+pub fn copy_bytes_from_user(pt: &PageTable, src: usize, len: usize) -> Result<Vec<u8>, Errno> {
+    let mut buf = Vec::new();
+    for_each_user_page(pt, src, len, PTEFlags::valid().readable(), |pa, chunk| {
+        buf.extend_from_slice(unsafe { core::slice::from_raw_parts(pa, chunk) })
+    })?;
+    Ok(buf)
+}
+
+/// Copy the string of `len` bytes at the user address `src` into a kernel string.
+/// Returns `EINVAL` if it isn't valid UTF-8.
+pub fn copy_str_from_user(pt: &PageTable, src: usize, len: usize) -> Result<String, Errno> {
+    String::from_utf8(copy_bytes_from_user(pt, src, len)?).map_err(|_| Errno::EINVAL)
+}
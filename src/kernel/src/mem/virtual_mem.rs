@@ -151,10 +151,19 @@ impl PTEFlags {
         PTEFlags(self.0 | PageTableEntry::U_FLAG_MASK)
     }
 
+    /// The mapped frame belongs to the page table, and is freed along with it
+    pub fn owned(self) -> Self {
+        PTEFlags(self.0 | PageTableEntry::O_FLAG_MASK)
+    }
+
     pub fn is_valid(&self) -> bool {
         (self.0 & PageTableEntry::V_FLAG_MASK) > 0
     }
 
+    pub fn is_userable(&self) -> bool {
+        (self.0 & PageTableEntry::U_FLAG_MASK) > 0
+    }
+
     pub fn is_redirect(&self) -> bool {
         !self.is_readable() && !self.is_writable() && !self.is_executable()
     }
@@ -192,6 +201,8 @@ impl PageTableEntry {
     const D_FLAG_MASK: u64 = 1 << 7;
     /// Free to use
     const RSW_MASK: u64 = 0b11 << 8;
+    /// "Owned" (uses the first RSW bit) - The frame belongs to the page table, see [`PTEFlags::owned`]
+    const O_FLAG_MASK: u64 = 1 << 8;
     const PPN0_MASK: u64 =
         0b0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0000_0111_1111_1100_0000_0000;
     const PPN1_MASK: u64 =
@@ -223,6 +234,19 @@ impl PageTableEntry {
         (self.0 & Self::X_FLAG_MASK) > 0
     }
 
+    pub const fn is_userable(&self) -> bool {
+        (self.0 & Self::U_FLAG_MASK) > 0
+    }
+
+    pub const fn is_owned(&self) -> bool {
+        (self.0 & Self::O_FLAG_MASK) > 0
+    }
+
+    /// The flags of the entry (the lower 10 bits)
+    pub const fn flags(&self) -> PTEFlags {
+        PTEFlags(self.0 & 0x3ff)
+    }
+
     /// Is this page entry a redirect to another page table
     pub const fn is_redirect(&self) -> bool {
         !self.is_readable() && !self.is_executable() && !self.is_writable()
@@ -17,6 +17,8 @@ pub const MTIME_OFFSET: usize = 0x0000_bff8;
 pub const MTIME_ADDR: usize = CLINT_BASE_ADDR + MTIME_OFFSET;
 /// The physical memory address of the `mtimecmp` registers.
 pub const MTIMECMP_ADDR: usize = CLINT_BASE_ADDR + MTIMECMPS_OFFSET;
+/// How many times a second `mtime` is incremented, qemu-virt runs it at 10MHz.
+pub const MTIME_FREQ: usize = 10_000_000;
 
 // UART
 
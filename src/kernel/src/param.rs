@@ -30,6 +30,27 @@ pub const HEAP_SIZE: u64 = PAGES_PER_HEAP * PAGE_SIZE as u64;
 /// The start of the heap for a process
 pub const HEAP_START: u64 = 0x2200_0000;
 
+/// The region of the address space of a process that `mmap` hands out pages from. It's above
+/// everything else a process maps: the image, the stack, the heap and the trampoline.
+pub const MMAP_START: usize = 0x1_0000_0000;
+pub const MMAP_END: usize = 0x10_0000_0000;
+
+/// The maximum length (in bytes) of a process name, longer names are cut
+pub const PROC_NAME_LEN: usize = 16;
+
+/// The maximum amount of files a process can have open at a time
+pub const NOFILE: usize = 16;
+
+/// The maximum amount of arguments that can be passed to a program through `exec`
+pub const MAXARG: usize = 16;
+
+/// The amount of priority levels of the scheduler, 0 is the highest
+pub const NPRIO: usize = 4;
+/// How many timer ticks a process may run at each priority level before it's demoted
+pub const TIME_SLICES: [usize; NPRIO] = [1, 2, 4, 8];
+/// Every how many timer ticks all of the processes are boosted back to their highest priority
+pub const BOOST_INTERVAL: usize = 50;
+
 /// The maximum amount of active processes at a time
 pub const NPROC: usize = ProcId::max_value() as usize;
 // pub const NPROC: usize = 100;
@@ -1,26 +1,54 @@
 use crate::{
     arch::registers::tp,
     cprintln,
-    elf_parse::ParsedExecutable,
+    cpu::{ccpu, cpus},
+    elf_parse::{parse_executable_file, ParsedExecutable},
+    errno::Errno,
+    files::{copy_file_to_ram, fd::FdTable, lookup, FileRef, DIRECTORY, ROOT_DIR},
     mem::{
         alloc_frame,
-        paging::{zerod_frame, PageTable, PageTableLevel},
+        paging::{Frame, PageTable, PageTableLevel},
+        uaccess::copy_to_user,
         virtual_mem::{PTEFlags, PhysAddr, VirtAddr},
     },
     memlayout::{TRAMPOLINE_VADDR, TRAPFRAME_VADDR},
-    param::{ProcId, HEAP_SIZE, HEAP_START, NPROC, PAGE_SIZE, STACK_SIZE},
-    scheduler::scheduler,
+    param::{
+        ProcId, HEAP_SIZE, HEAP_START, MAXARG, NCPU, NPRIO, NPROC, PAGE_SIZE, PROC_NAME_LEN,
+        STACK_SIZE, TIME_SLICES,
+    },
+    scheduler,
+    swtch::swtch,
     trampoline::trampoline,
+    trap::user_proc_entry,
 };
-use alloc::boxed::Box;
+use alloc::{boxed::Box, vec::Vec};
 use core::{
     cell::Cell,
+    fmt, hint,
     mem::zeroed,
-    sync::atomic::{AtomicUsize, Ordering},
+    ops::Deref,
+    slice, str,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
+use elf::abi::PT_LOAD;
+use spin::{Mutex, MutexGuard};
 
 const INACTIVE_PROC_NAME: &str = "X";
 
+/// The process that adopts orphaned processes: the kernel process, which is allocated at boot
+/// and never exits. It never waits for its children, so they are freed as soon as they exit.
+pub const INIT_PROC_ID: ProcId = 0;
+
+/// Guards the parent-child relationships between processes, must be held when changing
+/// [`Process::parent`], or when a process becomes a zombie or gets freed by its parent.
+static WAIT_LOCK: Mutex<()> = Mutex::new(());
+
+/// The affinity mask that lets a process run on every hart
+pub const ALL_CPUS: usize = (1 << NCPU) - 1;
+
+/// The value of [`Process::cpu`] for a process that wasn't queued yet
+const NO_CPU: usize = usize::MAX;
+
 pub static mut PROCS_ADDR: usize = 0;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -30,14 +58,70 @@ pub enum ProcStatus {
     Inactive = 1,
     Runnable = 2,
     Running = 3,
+    /// The process has exited, but its parent hasn't collected its exit code yet
+    Zombie = 4,
+    /// The process is blocked until someone calls [`wakeup`] on its wait channel
+    Sleeping = 5,
 }
 
 #[repr(transparent)]
 pub struct AtomicProcStatus(AtomicUsize);
 
+/// The name of a process, kept inside the process so it can be built at runtime.
+/// Names longer than [`PROC_NAME_LEN`] bytes are cut.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct ProcName {
+    bytes: [u8; PROC_NAME_LEN],
+    len: usize,
+}
+
+impl ProcName {
+    pub fn new(name: &str) -> Self {
+        let mut len = name.len().min(PROC_NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut bytes = [0; PROC_NAME_LEN];
+        bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+        ProcName { bytes, len }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever built from a `str`, cut on a char boundary
+        unsafe { str::from_utf8_unchecked(&self.bytes[..self.len]) }
+    }
+}
+
+impl Deref for ProcName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for ProcName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A snapshot of a process, as copied into user memory by the `ps` syscall
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct ProcInfo {
+    pub pid: usize,
+    pub parent: usize,
+    pub status: ProcStatus,
+    /// The timer ticks the process has been running for
+    pub cpu_ticks: usize,
+    pub name: ProcName,
+}
+
 pub struct Process {
     /// The name of the process, inactive processes are named "X"
-    name: Cell<&'static str>,
+    name: Cell<ProcName>,
     /// Indexes [`ProcTable`]
     pub id: ProcId,
     /// The status of the process
@@ -48,6 +132,37 @@ pub struct Process {
     pub page_table: *mut PageTable,
     /// After [`init_procs`] is called, must be valid.
     pub trapframe: *mut Trapframe,
+    /// The saved kernel registers of the process, [`swtch`] to it to resume the process.
+    /// After [`init_procs`] is called, must be valid.
+    pub context: *mut Context,
+    /// The process that created this process, or [`INIT_PROC_ID`] if it was orphaned.
+    parent: AtomicUsize,
+    /// The exit code of the process, valid once it's a [`ProcStatus::Zombie`].
+    exit_code: AtomicUsize,
+    /// What the process is waiting for, valid while it's [`ProcStatus::Sleeping`].
+    chan: AtomicUsize,
+    /// Is a hart currently executing on the process' kernel stack. A hart only lets go of it
+    /// once it has switched back to its scheduler.
+    pub on_cpu: AtomicBool,
+    /// The current priority level of the process in the scheduler, 0 is the highest.
+    priority: AtomicUsize,
+    /// The highest priority level the process can be at, see [`Process::set_nice`].
+    nice: AtomicUsize,
+    /// The timer ticks the process has been running for at its current priority level.
+    ticks: AtomicUsize,
+    /// The timer ticks the process has been running for since it was created.
+    cpu_ticks: AtomicUsize,
+    /// A bit mask of the harts the process may run on, see [`Process::set_affinity`].
+    affinity: AtomicUsize,
+    /// The hart whose run queue the process is on, or that ran it last. [`NO_CPU`] if the
+    /// process hasn't been queued yet.
+    cpu: AtomicUsize,
+    /// The files the process has open
+    fds: Mutex<FdTable>,
+    /// The end of the heap of the process (the program break), see [`Process::set_brk`].
+    brk: AtomicUsize,
+    /// The directory relative paths start at, see [`Process::set_cwd`].
+    cwd: Mutex<FileRef>,
 }
 
 pub struct ProcTable([Process; NPROC]);
@@ -77,17 +192,32 @@ impl Process {
         let pt: &mut PageTable = Box::leak(unsafe { Box::new_zeroed().assume_init() });
         let tf: &mut Trapframe = Box::leak(unsafe { Box::new_zeroed().assume_init() });
         let ks: &mut [u8; STACK_SIZE] = Box::leak(unsafe { Box::new_zeroed().assume_init() });
+        let ctx: &mut Context = Box::leak(Box::new(Context::zeroed()));
         Process {
-            name: Cell::new(INACTIVE_PROC_NAME),
+            name: Cell::new(ProcName::new(INACTIVE_PROC_NAME)),
             id,
             status: AtomicProcStatus::new(ProcStatus::Unused),
             page_table: pt as *mut _,
             trapframe: tf as *mut _,
             kernel_stack: ks as *mut _,
+            context: ctx as *mut _,
+            parent: AtomicUsize::new(INIT_PROC_ID as usize),
+            exit_code: AtomicUsize::new(0),
+            chan: AtomicUsize::new(0),
+            on_cpu: AtomicBool::new(false),
+            priority: AtomicUsize::new(0),
+            nice: AtomicUsize::new(0),
+            ticks: AtomicUsize::new(0),
+            cpu_ticks: AtomicUsize::new(0),
+            affinity: AtomicUsize::new(ALL_CPUS),
+            cpu: AtomicUsize::new(NO_CPU),
+            fds: Mutex::new(FdTable::new()),
+            brk: AtomicUsize::new(HEAP_START as usize),
+            cwd: Mutex::new(ROOT_DIR),
         }
     }
 
-    pub fn name(&self) -> &str {
+    pub fn name(&self) -> ProcName {
         self.name.get()
     }
 
@@ -101,139 +231,576 @@ impl Process {
             .expect("init_procs wasn't called before trying to access the process")
     }
 
-    pub fn exit(&self, exit_code: usize) {
+    pub fn fds(&self) -> MutexGuard<'_, FdTable> {
+        self.fds.lock()
+    }
+
+    pub fn parent(&self) -> ProcId {
+        self.parent.load(Ordering::SeqCst) as ProcId
+    }
+
+    pub fn priority(&self) -> usize {
+        self.priority.load(Ordering::SeqCst)
+    }
+
+    pub fn nice(&self) -> usize {
+        self.nice.load(Ordering::SeqCst)
+    }
+
+    /// Make `nice` the highest priority level the process can reach, and move it there. A
+    /// higher nice value leaves more of the harts to the other processes.
+    /// Returns `EINVAL` if `nice` isn't a priority level.
+    pub fn set_nice(&self, nice: usize) -> Result<(), Errno> {
+        if nice >= NPRIO {
+            return Err(Errno::EINVAL);
+        }
+        scheduler::set_nice(self, nice);
+        Ok(())
+    }
+
+    /// Set the nice value of the process, and put it back on that level with a fresh time
+    /// slice. Doesn't move the process in the run queue.
+    pub(crate) fn reset_priority(&self, nice: usize) {
+        self.nice.store(nice, Ordering::SeqCst);
+        self.priority.store(nice, Ordering::SeqCst);
+        self.ticks.store(0, Ordering::SeqCst);
+    }
+
+    pub fn affinity(&self) -> usize {
+        self.affinity.load(Ordering::SeqCst)
+    }
+
+    /// May the process run on the hart `hart_id`
+    pub fn allowed_on(&self, hart_id: usize) -> bool {
+        self.affinity() & (1 << hart_id) != 0
+    }
+
+    /// Pin the process to the harts that have their bit set in `mask`. Returns `EINVAL` if none
+    /// of those harts is online.
+    pub fn set_affinity(&self, mask: usize) -> Result<(), Errno> {
+        if !cpus()
+            .iter()
+            .enumerate()
+            .any(|(id, cpu)| mask & (1 << id) != 0 && cpu.is_online())
+        {
+            return Err(Errno::EINVAL);
+        }
+        self.affinity.store(mask & ALL_CPUS, Ordering::SeqCst);
+        scheduler::migrate(self);
+        Ok(())
+    }
+
+    /// The hart whose run queue the process is on, or that ran it last
+    pub fn cpu(&self) -> Option<usize> {
+        Some(self.cpu.load(Ordering::SeqCst)).filter(|cpu| *cpu != NO_CPU)
+    }
+
+    pub(crate) fn set_cpu(&self, hart_id: usize) {
+        self.cpu.store(hart_id, Ordering::SeqCst);
+    }
+
+    /// Count a timer tick that came while the process was running. Returns whether the process
+    /// should give up the hart: either it used up its time slice, and is demoted to the next
+    /// priority level, or a process with a higher priority is waiting, or it isn't allowed to
+    /// run on the current hart anymore.
+    pub fn tick(&self) -> bool {
+        if !self.allowed_on(cpuid()) {
+            return true;
+        }
+        self.cpu_ticks.fetch_add(1, Ordering::SeqCst);
+        let priority = self.priority();
+        if self.ticks.fetch_add(1, Ordering::SeqCst) + 1 >= TIME_SLICES[priority] {
+            self.ticks.store(0, Ordering::SeqCst);
+            self.priority
+                .store((priority + 1).min(NPRIO - 1), Ordering::SeqCst);
+            return true;
+        }
+        scheduler::has_higher_priority(priority)
+    }
+
+    /// The working directory of the process
+    pub fn cwd(&self) -> FileRef {
+        *self.cwd.lock()
+    }
+
+    /// Change the working directory to `path`. Returns `ENOTDIR` if it isn't a directory.
+    pub fn set_cwd(&self, path: &str) -> Result<(), Errno> {
+        let dir = lookup(self.cwd(), path)?;
+        if dir.file_type != DIRECTORY {
+            return Err(Errno::ENOTDIR);
+        }
+        *self.cwd.lock() = FileRef::of(&dir);
+        Ok(())
+    }
+
+    /// The end of the heap of the process
+    pub fn brk(&self) -> usize {
+        self.brk.load(Ordering::SeqCst)
+    }
+
+    /// Move the end of the heap of the process to `new_brk`. The heap starts at [`HEAP_START`],
+    /// and grows (or shrinks) a page at a time, every page is backed by a new frame.
+    /// Returns the new end, or `ENOMEM` if it's outside of the heap region or there isn't enough
+    /// memory, in which case the heap is left as it was.
+    pub fn set_brk(&self, new_brk: usize) -> Result<usize, Errno> {
+        let heap_start = HEAP_START as usize;
+        if !(heap_start..=heap_start + HEAP_SIZE as usize).contains(&new_brk) {
+            return Err(Errno::ENOMEM);
+        }
+        let pt = unsafe { self.page_table.as_mut().unwrap() };
+        let old_end = page_round_up(self.brk());
+        let new_end = page_round_up(new_brk);
+        for page in (old_end..new_end).step_by(PAGE_SIZE) {
+            let Some(frame) = (unsafe { alloc_frame() }) else {
+                for page in (old_end..page).step_by(PAGE_SIZE) {
+                    pt.unmap(VirtAddr::from_raw(page as u64));
+                }
+                return Err(Errno::ENOMEM);
+            };
+            pt.strong_map(
+                VirtAddr::from_raw(page as u64),
+                PhysAddr::from_raw(frame.as_ptr() as u64),
+                PTEFlags::valid().readable().writable().userable().owned(),
+                PageTableLevel::L2,
+            );
+        }
+        for page in (new_end..old_end).step_by(PAGE_SIZE) {
+            pt.unmap(VirtAddr::from_raw(page as u64));
+        }
+        self.brk.store(new_brk, Ordering::SeqCst);
+        Ok(new_brk)
+    }
+
+    /// The processes (that are in use) that have this process as their parent
+    fn children(&self) -> impl Iterator<Item = &Process> {
+        procs().0.iter().filter(|p| {
+            p.id != self.id
+                && p.parent() == self.id
+                && p.status.load(Ordering::SeqCst) != ProcStatus::Unused
+        })
+    }
+
+    /// Stop the process for good. The process stays a [`ProcStatus::Zombie`] until its parent
+    /// collects the exit code with [`Process::wait`], and its own children are given to the
+    /// init process.
+    pub fn exit(&self, exit_code: usize) -> ! {
         cprintln!(
             "Process `{}` (id={}) exited with code {}",
             self.name(),
             self.id,
             exit_code
         );
-        self.name.replace(INACTIVE_PROC_NAME);
+        // The process won't return to user mode, the user memory and the open files can go
+        unsafe { self.page_table.as_mut().unwrap() }.clear();
+        self.fds().clear();
+        {
+            let _guard = WAIT_LOCK.lock();
+            for child in self.children() {
+                child.parent.store(INIT_PROC_ID as usize, Ordering::SeqCst);
+                if child.status.load(Ordering::SeqCst) == ProcStatus::Zombie {
+                    child.free();
+                }
+            }
+            self.exit_code.store(exit_code, Ordering::SeqCst);
+            if self.parent() == INIT_PROC_ID {
+                // Nobody is going to wait for the process
+                self.free();
+            } else {
+                self.status
+                    .compare_exchange(
+                        ProcStatus::Running,
+                        ProcStatus::Zombie,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    )
+                    .unwrap();
+                wakeup(proc(self.parent()).wait_chan());
+            }
+        }
+
+        self.sched();
+        unreachable!("Exited process `{}` was resumed", self.id);
+    }
+
+    /// Wait for a child of the process to exit, and free it. If `pid` is `Some`, wait only for
+    /// that child. Returns the id and the exit code of the child, or `None` if the process has
+    /// no (matching) children.
+    pub fn wait(&self, pid: Option<ProcId>) -> Option<(ProcId, usize)> {
+        let mut guard = WAIT_LOCK.lock();
+        loop {
+            let mut children = self
+                .children()
+                .filter(|child| pid.is_none_or(|pid| pid == child.id))
+                .peekable();
+            children.peek()?;
+            let is_zombie =
+                |child: &&Process| child.status.load(Ordering::SeqCst) == ProcStatus::Zombie;
+            if let Some(zombie) = children.find(is_zombie) {
+                let exit_code = zombie.exit_code.load(Ordering::SeqCst);
+                zombie.free();
+                return Some((zombie.id, exit_code));
+            }
+            // Exiting children wake their parent up
+            guard = self.sleep(self.wait_chan(), &WAIT_LOCK, guard);
+        }
+    }
+
+    /// The channel a process sleeps on while waiting for its children
+    fn wait_chan(&self) -> usize {
+        self as *const _ as usize
+    }
+
+    /// Release `lock` and block the process until [`wakeup`] is called on `chan`, then take
+    /// the lock again. The process is marked as sleeping while the lock is still held, so a
+    /// wakeup that's done while holding the lock can't be missed.
+    pub fn sleep<'a, T>(
+        &self,
+        chan: usize,
+        lock: &'a Mutex<T>,
+        guard: MutexGuard<'a, T>,
+    ) -> MutexGuard<'a, T> {
+        self.chan.store(chan, Ordering::SeqCst);
         self.status
             .compare_exchange(
                 ProcStatus::Running,
-                ProcStatus::Unused,
+                ProcStatus::Sleeping,
                 Ordering::SeqCst,
                 Ordering::SeqCst,
             )
             .unwrap();
+        drop(guard);
+        self.sched();
+        lock.lock()
+    }
 
-        scheduler(cpuid());
+    /// Give the slot of the process back to the process table.
+    fn free(&self) {
+        self.name.set(ProcName::new(INACTIVE_PROC_NAME));
+        self.status.store(ProcStatus::Unused, Ordering::SeqCst);
     }
 
-    /// After calling this function, the process will be ready to run
-    pub fn activate<'a>(&self, exe: &ParsedExecutable<'a>) {
-        if self
-            .status
+    /// Stop running the process and hand the hart back to the scheduler, the call returns once
+    /// some hart picks the process up again.
+    pub fn yield_cpu(&self) {
+        self.status
             .compare_exchange(
-                ProcStatus::Inactive,
+                ProcStatus::Running,
                 ProcStatus::Runnable,
                 Ordering::SeqCst,
                 Ordering::SeqCst,
             )
-            .is_ok()
-        {
+            .unwrap();
+        scheduler::enqueue(self);
+        self.sched();
+    }
+
+    /// Save the kernel context of the process and switch to the scheduler of the current hart.
+    /// The status of the process must already have been changed from [`ProcStatus::Running`],
+    /// and interrupts must be disabled.
+    fn sched(&self) {
+        unsafe { swtch(self.context, &ccpu().context) };
+    }
+
+    /// After calling this function, the process will be ready to run
+    pub fn activate<'a>(&self, exe: &ParsedExecutable<'a>) {
+        if self.status.load(Ordering::SeqCst) == ProcStatus::Inactive {
             let pt = unsafe { self.page_table.as_mut().unwrap() };
             let tf = unsafe { self.trapframe.as_mut().unwrap() };
-            let ks = unsafe { self.kernel_stack.as_mut().unwrap() };
             *pt = unsafe { zeroed() };
             *tf = unsafe { zeroed() };
-            ks.fill(0);
+            self.init_context();
             // The program counter needs to start at the start of the code section
             let program_counter = exe.entry_point as u64;
-            let file_base = exe.file_data.as_ptr() as usize;
-            let mut data_end = 0;
-            // Map the text section (code of the process), it needs to be readable and executable
-            for seg in exe.segs {
-                let flags = PTEFlags::valid()
-                    .readable()
-                    .writable()
-                    .executable()
-                    .userable();
-                // if seg.p_flags & (1 << 2) != 0 {
-                //     flags = flags.readable();
-                // }
-                // if seg.p_flags & (1 << 1) != 0 {
-                //     flags = flags.writable();
-                // }
-                // if seg.p_flags & (1 << 0) != 0 {
-                //     flags = flags.executable();
-                // }
-                let vaddr_base = seg.p_vaddr;
-                let size = seg.p_memsz;
-
-                for offset in (0..size).into_iter().step_by(PAGE_SIZE) {
-                    let va = vaddr_base + offset;
-                    let pa = file_base as u64 + offset + seg.p_offset;
+            let stack_pointer =
+                map_user_image(pt, exe).expect("Couldn't load the executable into memory");
+            self.map_kernel_pages(pt);
+
+            tf.sp = stack_pointer as usize;
+            tf.epc = program_counter as usize;
+            // Only now that everything is in place the scheduler may pick the process up
+            self.status.store(ProcStatus::Runnable, Ordering::SeqCst);
+            scheduler::enqueue(self);
+        } else {
+            panic!("Can't activate Unused or Active Proc");
+        }
+    }
+
+    /// Create a copy of the process in a new slot. Every user page of the process is deep-copied
+    /// into new frames, and the copy resumes from the same trapframe, but with `a0` set to 0.
+    /// Returns the id of the new process, or `None` if there is no free slot or memory.
+    pub fn fork(&self) -> Option<ProcId> {
+        let child = &procs()[procs().alloc_proc(&self.name())?];
+        let pt = unsafe { child.page_table.as_mut().unwrap() };
+        let tf = unsafe { child.trapframe.as_mut().unwrap() };
+        *pt = unsafe { zeroed() };
+
+        let mut out_of_memory = false;
+        self.pagetable().for_each_leaf(&mut |va, pte| {
+            // The trapframe and trampoline don't belong to the process, each process maps its
+            // own. Every other page does, even the ones that are hidden from user mode.
+            if out_of_memory || !pte.is_owned() {
+                return;
+            }
+            match unsafe { alloc_frame() } {
+                Some(frame) => {
+                    unsafe {
+                        frame
+                            .as_ptr()
+                            .copy_from_nonoverlapping(pte.frame_addr() as *const Frame, 1)
+                    };
                     pt.strong_map(
-                        VirtAddr::from_raw(va),
-                        PhysAddr::from_raw(pa),
-                        flags,
+                        va,
+                        PhysAddr::from_raw(frame.as_ptr() as u64),
+                        pte.flags().owned(),
                         PageTableLevel::L2,
                     );
                 }
+                None => out_of_memory = true,
+            }
+        });
+        if out_of_memory {
+            pt.clear();
+            child.free();
+            return None;
+        }
 
-                if seg.p_type <= 0x00000007 {
-                    data_end = vaddr_base + size;
-                }
+        child.map_kernel_pages(pt);
+        child.init_context();
+        {
+            let _guard = WAIT_LOCK.lock();
+            child.parent.store(self.id as usize, Ordering::SeqCst);
+        }
+        *tf = *self.trapframe();
+        *child.fds() = self.fds().clone();
+        // `fork` returns 0 in the child
+        tf.a0 = 0;
+        // The child starts over at the highest priority the parent may have, on the harts the
+        // parent may run on
+        child.reset_priority(self.nice());
+        child.affinity.store(self.affinity(), Ordering::SeqCst);
+        // The heap pages were copied with the rest of the user memory
+        child.brk.store(self.brk(), Ordering::SeqCst);
+        *child.cwd.lock() = self.cwd();
+        child.status.store(ProcStatus::Runnable, Ordering::SeqCst);
+        scheduler::enqueue(child);
+        Some(child.id)
+    }
+
+    /// Replace the program the process is running with the executable at the path `file_name`
+    /// on the disk.
+    /// The new program starts with `a0` holding the amount of arguments, and `a1` pointing to an
+    /// array of `(ptr, len)` pairs, one for each argument in `args`.
+    /// If the file is missing, isn't a valid executable or doesn't fit in memory, the process is
+    /// left untouched.
+    pub fn exec(&self, file_name: &str, args: &[Vec<u8>]) -> Result<(), Errno> {
+        let args_size = args.iter().map(|arg| arg.len()).sum::<usize>()
+            + args.len() * size_of::<(usize, usize)>();
+        if args.len() > MAXARG || args_size > PAGE_SIZE {
+            return Err(Errno::E2BIG);
+        }
+        let file_meta = lookup(self.cwd(), file_name)?;
+        let data = copy_file_to_ram(FileRef::of(&file_meta))?;
+        let exe = parse_executable_file(&data).ok_or(Errno::ENOEXEC)?;
+
+        // Build the new image on the side, so nothing changes if it fails
+        let new_pt: &mut PageTable = Box::leak(unsafe { Box::new_zeroed().assume_init() });
+        let stack_pointer = match map_user_image(new_pt, &exe)
+            .and_then(|stack_pointer| push_args(new_pt, stack_pointer, args))
+        {
+            Ok(stack_pointer) => stack_pointer,
+            Err(errno) => {
+                new_pt.clear();
+                drop(unsafe { Box::from_raw(new_pt as *mut PageTable) });
+                return Err(errno);
             }
+        };
+        self.map_kernel_pages(new_pt);
+
+        let pt = unsafe { self.page_table.as_mut().unwrap() };
+        let tf = unsafe { self.trapframe.as_mut().unwrap() };
+        core::mem::swap(pt, new_pt);
+        // `new_pt` now holds the old root table, free the old image
+        new_pt.clear();
+        drop(unsafe { Box::from_raw(new_pt as *mut PageTable) });
+        *tf = unsafe { zeroed() };
+        tf.epc = exe.entry_point;
+        tf.sp = stack_pointer as usize;
+        tf.a0 = args.len();
+        tf.a1 = stack_pointer as usize;
+        self.brk.store(HEAP_START as usize, Ordering::SeqCst);
+        // The process is named after the program it runs
+        let program = file_name.rsplit('/').next().unwrap_or(file_name);
+        self.name.set(ProcName::new(program));
+        Ok(())
+    }
+
+    /// Set up the kernel context, so the first switch to the process will "return" to
+    /// [`user_proc_entry`] on its own kernel stack.
+    fn init_context(&self) {
+        let ks = unsafe { self.kernel_stack.as_mut().unwrap() };
+        let ctx = unsafe { self.context.as_mut().unwrap() };
+        ks.fill(0);
+        *ctx = Context::zeroed();
+        ctx.ra = user_proc_entry as *const () as usize;
+        ctx.sp = self.kernel_stack as usize + STACK_SIZE;
+    }
+
+    /// Map the pages every process needs for moving between user and kernel mode: its trapframe
+    /// and the trampoline.
+    fn map_kernel_pages(&self, pt: &mut PageTable) {
+        // map the trapframe
+        pt.strong_map(
+            VirtAddr::from_raw(TRAPFRAME_VADDR as u64),
+            PhysAddr::from_raw(self.trapframe as u64),
+            PTEFlags::valid().readable().writable(),
+            PageTableLevel::L2,
+        );
+        // map the trampoline
+        pt.strong_map(
+            VirtAddr::from_raw(TRAMPOLINE_VADDR as u64),
+            PhysAddr::from_raw(trampoline as *const () as u64),
+            PTEFlags::valid().readable().executable(),
+            PageTableLevel::L2,
+        );
+    }
+}
 
-            // Allocate and map the stack, take into account that we want to keep at least
-            // a single non-mapped region of memory (with the size of a single page) between the
-            // stack and the data, so in case of a stack overflow, a stack overflow exception
-            // will occur and no data will be corrupted.
-            let stack_pointer = {
-                let stack_addr = 0x50000000 + data_end + PAGE_SIZE as u64;
-                for offset in (0..STACK_SIZE as u64).into_iter().step_by(PAGE_SIZE) {
-                    // let frame = unsafe { Box::<Page>::new_zeroed().assume_init() };
-                    let frame = Box::new(zerod_frame());
-                    let frame_addr = Box::into_raw(frame) as usize;
-                    if let Some(_) = pt.strong_map(
-                        VirtAddr::from_raw(stack_addr as u64 + offset),
-                        PhysAddr::from_raw(frame_addr as u64),
-                        PTEFlags::valid().readable().writable().userable(),
+/// Copy the loadable segments of the executable into newly allocated frames, and map them and a
+/// newly allocated stack into the page table. Every frame is owned by the page table.
+/// Returns the initial stack pointer. Returns `ENOEXEC` if the executable is malformed, and
+/// `ENOMEM` if there isn't enough memory, in which case the page table might be left partially
+/// mapped.
+fn map_user_image(pt: &mut PageTable, exe: &ParsedExecutable) -> Result<u64, Errno> {
+    let mut data_end = 0;
+    // Map the text section (code of the process), it needs to be readable and executable
+    for seg in exe.segs.iter().filter(|seg| seg.p_type == PT_LOAD) {
+        let flags = PTEFlags::valid()
+            .readable()
+            .writable()
+            .executable()
+            .userable()
+            .owned();
+        // if seg.p_flags & (1 << 2) != 0 {
+        //     flags = flags.readable();
+        // }
+        // if seg.p_flags & (1 << 1) != 0 {
+        //     flags = flags.writable();
+        // }
+        // if seg.p_flags & (1 << 0) != 0 {
+        //     flags = flags.executable();
+        // }
+        let vaddr_base = seg.p_vaddr;
+        let size = seg.p_memsz;
+        let file_data = exe
+            .file_data
+            .get(seg.p_offset as usize..(seg.p_offset + seg.p_filesz) as usize)
+            .ok_or(Errno::ENOEXEC)?;
+
+        let first_page = vaddr_base & !(PAGE_SIZE as u64 - 1);
+        for page in (first_page..vaddr_base + size).step_by(PAGE_SIZE) {
+            let va = VirtAddr::from_raw(page);
+            // Two segments may share a page
+            let frame_addr = match pt.entry(va) {
+                Some(pte) => pte.frame_addr(),
+                None => {
+                    let frame_addr = unsafe { alloc_frame() }.ok_or(Errno::ENOMEM)?.as_ptr() as u64;
+                    pt.strong_map(
+                        va,
+                        PhysAddr::from_raw(frame_addr),
+                        flags,
                         PageTableLevel::L2,
-                    ) {
-                        panic!("Stack section overlaps with data segments");
-                    }
+                    );
+                    frame_addr
                 }
-                stack_addr + STACK_SIZE as u64
             };
+            // Copy the part of the segment's file data that lands in this page, the rest of
+            // the page stays zeroed (the .bss part of the segment)
+            let copy_start = page.max(vaddr_base);
+            let copy_end = (page + PAGE_SIZE as u64).min(vaddr_base + file_data.len() as u64);
+            if copy_start < copy_end {
+                let src = &file_data[(copy_start - vaddr_base) as usize..];
+                unsafe {
+                    ((frame_addr + copy_start - page) as *mut u8)
+                        .copy_from_nonoverlapping(src.as_ptr(), (copy_end - copy_start) as usize)
+                };
+            }
+        }
 
-            // Allocate and map the heap
-            // {
-            //     for offset in (0..HEAP_SIZE).into_iter().step_by(PAGE_SIZE) {
-            //         let frame_addr = unsafe { alloc_frame() }.unwrap().as_ptr() as u64;
-            //         if let Some(_) = pt.strong_map(
-            //             VirtAddr::from_raw(HEAP_START + offset as u64),
-            //             PhysAddr::from_raw(frame_addr),
-            //             PTEFlags::valid().readable().writable().userable(),
-            //             PageTableLevel::L2,
-            //         ) {
-            //             panic!("Heap section overlaps with data segments");
-            //         }
-            //     }
-            // }
-
-            // map the trapframe
-            pt.strong_map(
-                VirtAddr::from_raw(TRAPFRAME_VADDR as u64),
-                PhysAddr::from_raw(self.trapframe as u64),
-                PTEFlags::valid().readable().writable(),
-                PageTableLevel::L2,
-            );
-            // map the trampoline
+        data_end = data_end.max(vaddr_base + size);
+    }
+
+    // Allocate and map the stack, take into account that we want to keep at least
+    // a single non-mapped region of memory (with the size of a single page) between the
+    // stack and the data, so in case of a stack overflow, a stack overflow exception
+    // will occur and no data will be corrupted.
+    let stack_pointer = {
+        // The last segment rarely ends on a page boundary, and the stack has to start on one
+        let data_end = page_round_up(data_end as usize) as u64;
+        let stack_addr = 0x50000000 + data_end + PAGE_SIZE as u64;
+        assert!(stack_addr.is_multiple_of(PAGE_SIZE as u64));
+        for offset in (0..STACK_SIZE as u64).into_iter().step_by(PAGE_SIZE) {
+            let va = VirtAddr::from_raw(stack_addr + offset);
+            if pt.entry(va).is_some() {
+                // The stack section overlaps with data segments
+                return Err(Errno::ENOEXEC);
+            }
+            let frame_addr = unsafe { alloc_frame() }.ok_or(Errno::ENOMEM)?.as_ptr() as u64;
             pt.strong_map(
-                VirtAddr::from_raw(TRAMPOLINE_VADDR as u64),
-                PhysAddr::from_raw(trampoline as u64),
-                PTEFlags::valid().readable().executable(),
+                va,
+                PhysAddr::from_raw(frame_addr),
+                PTEFlags::valid().readable().writable().userable().owned(),
                 PageTableLevel::L2,
             );
+        }
+        stack_addr + STACK_SIZE as u64
+    };
 
-            tf.sp = stack_pointer as usize;
-            tf.epc = program_counter as usize;
-        } else {
-            panic!("Can't activate Unused or Active Proc");
+    Ok(stack_pointer)
+}
+
+/// Push the arguments to the top of the stack at `stack_pointer`, followed by the array that
+/// points to them. Returns the new stack pointer, which points to the array.
+fn push_args(pt: &PageTable, mut stack_pointer: u64, args: &[Vec<u8>]) -> Result<u64, Errno> {
+    let mut argv = [(0usize, 0usize); MAXARG];
+    for (arg, (ptr, len)) in args.iter().zip(argv.iter_mut()) {
+        stack_pointer -= arg.len() as u64;
+        copy_to_user(pt, stack_pointer as usize, arg)?;
+        *ptr = stack_pointer as usize;
+        *len = arg.len();
+    }
+    stack_pointer -= (args.len() * size_of::<(usize, usize)>()) as u64;
+    stack_pointer &= !0xf;
+    let argv_bytes = unsafe {
+        slice::from_raw_parts(
+            argv.as_ptr() as *const u8,
+            args.len() * size_of::<(usize, usize)>(),
+        )
+    };
+    copy_to_user(pt, stack_pointer as usize, argv_bytes)?;
+    Ok(stack_pointer)
+}
+
+/// Round `addr` up to the start of the next page, unless it's already the start of a page
+fn page_round_up(addr: usize) -> usize {
+    (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// Make every process that is sleeping on `chan` runnable again.
+pub fn wakeup(chan: usize) {
+    for proc in procs().0.iter() {
+        if proc.status.load(Ordering::SeqCst) == ProcStatus::Sleeping
+            && proc.chan.load(Ordering::SeqCst) == chan
+            && proc
+                .status
+                .compare_exchange(
+                    ProcStatus::Sleeping,
+                    ProcStatus::Runnable,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_ok()
+        {
+            scheduler::enqueue(proc);
         }
     }
 }
@@ -243,7 +810,25 @@ impl ProcTable {
         ProcTable(core::array::from_fn(|i| Process::new_inactive(i as ProcId)))
     }
 
-    pub fn alloc_proc(&self, name: &'static str) -> Option<ProcId> {
+    pub fn iter(&self) -> impl Iterator<Item = &Process> {
+        self.0.iter()
+    }
+
+    /// Describe every process that is in use, see [`ProcInfo`]
+    pub fn snapshot(&self) -> Vec<ProcInfo> {
+        self.iter()
+            .filter(|proc| proc.status.load(Ordering::SeqCst) != ProcStatus::Unused)
+            .map(|proc| ProcInfo {
+                pid: proc.id as usize,
+                parent: proc.parent() as usize,
+                status: proc.status.load(Ordering::SeqCst),
+                cpu_ticks: proc.cpu_ticks.load(Ordering::SeqCst),
+                name: proc.name(),
+            })
+            .collect()
+    }
+
+    pub fn alloc_proc(&self, name: &str) -> Option<ProcId> {
         for proc in &self.0 {
             if proc
                 .status
@@ -255,7 +840,19 @@ impl ProcTable {
                 )
                 .is_ok()
             {
-                proc.name.replace(name);
+                // The process that last used this slot might still be switching away from it
+                while proc.on_cpu.load(Ordering::SeqCst) {
+                    hint::spin_loop();
+                }
+                proc.name.set(ProcName::new(name));
+                proc.parent.store(INIT_PROC_ID as usize, Ordering::SeqCst);
+                proc.reset_priority(0);
+                proc.cpu_ticks.store(0, Ordering::SeqCst);
+                *proc.fds() = FdTable::with_console();
+                proc.affinity.store(ALL_CPUS, Ordering::SeqCst);
+                proc.cpu.store(NO_CPU, Ordering::SeqCst);
+                proc.brk.store(HEAP_START as usize, Ordering::SeqCst);
+                *proc.cwd.lock() = ROOT_DIR;
                 return Some(proc.id);
             }
         }
@@ -271,6 +868,48 @@ impl core::ops::Index<ProcId> for ProcTable {
     }
 }
 
+/// The callee-saved registers of a kernel thread (the scheduler of a hart, or a process running
+/// in the kernel), saved and restored by [`swtch`].
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct Context {
+    /*   0 */ pub ra: usize,
+    /*   8 */ pub sp: usize,
+    /*  16 */ pub s0: usize,
+    /*  24 */ pub s1: usize,
+    /*  32 */ pub s2: usize,
+    /*  40 */ pub s3: usize,
+    /*  48 */ pub s4: usize,
+    /*  56 */ pub s5: usize,
+    /*  64 */ pub s6: usize,
+    /*  72 */ pub s7: usize,
+    /*  80 */ pub s8: usize,
+    /*  88 */ pub s9: usize,
+    /*  96 */ pub s10: usize,
+    /* 104 */ pub s11: usize,
+}
+
+impl Context {
+    pub const fn zeroed() -> Self {
+        Context {
+            ra: 0,
+            sp: 0,
+            s0: 0,
+            s1: 0,
+            s2: 0,
+            s3: 0,
+            s4: 0,
+            s5: 0,
+            s6: 0,
+            s7: 0,
+            s8: 0,
+            s9: 0,
+            s10: 0,
+            s11: 0,
+        }
+    }
+}
+
 /// The saved values of the registers while executing in user mode.
 #[derive(Clone, Copy, Default, Debug)]
 #[repr(C, align(4096))]
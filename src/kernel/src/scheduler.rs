@@ -1,43 +1,229 @@
 use crate::*;
+use alloc::collections::VecDeque;
 use arch::asm::wfi;
-use arch::interrupts::s_enable;
-use arch::registers::{ra, sp};
-use core::arch::asm;
+use arch::interrupts::{s_disable, s_enable};
+use core::hint;
 use core::sync::atomic::*;
-use cpu::ccpu;
-use param::{ProcId, NPROC, STACK_SIZE};
-use proc::{cpuid, proc};
-use trap::user_proc_entry;
+use cpu::{ccpu, cpus};
+use param::{ProcId, BOOST_INTERVAL, NPRIO};
+use proc::{cpuid, proc, procs, ProcStatus, Process};
+use swtch::swtch;
 
-pub fn scheduler(_hart_id: usize) -> ! {
+/// Timer ticks counted by hart 0, drive the periodic priority boost and wake up sleeping
+/// processes
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// The run queue of a hart, part of the multi-level feedback queue. Holds one queue of runnable
+/// processes per priority level, a process is queued on the level of [`Process::priority`].
+/// Together, the run queues of the harts hold exactly the processes that are
+/// [`ProcStatus::Runnable`].
+pub struct RunQueue {
+    levels: [VecDeque<ProcId>; NPRIO],
+}
+
+impl RunQueue {
+    pub const fn new() -> Self {
+        RunQueue {
+            levels: [const { VecDeque::new() }; NPRIO],
+        }
+    }
+
+    fn push(&mut self, proc: &Process) {
+        self.levels[proc.priority()].push_back(proc.id);
+    }
+
+    /// Take the process that waited the longest on the highest non-empty level
+    fn pop(&mut self) -> Option<ProcId> {
+        self.levels.iter_mut().find_map(|level| level.pop_front())
+    }
+
+    /// Take the first process (by priority) that is allowed to run on the hart `hart_id`
+    fn pop_for(&mut self, hart_id: usize) -> Option<ProcId> {
+        self.levels.iter_mut().find_map(|level| {
+            let idx = level.iter().position(|id| proc(*id).allowed_on(hart_id))?;
+            level.remove(idx)
+        })
+    }
+
+    /// Take the process out of the queue, returns whether it was queued
+    fn remove(&mut self, proc_id: ProcId) -> bool {
+        for level in &mut self.levels {
+            if let Some(idx) = level.iter().position(|id| *id == proc_id) {
+                level.remove(idx);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The highest level that has a process waiting on it
+    fn highest_priority(&self) -> Option<usize> {
+        self.levels.iter().position(|level| !level.is_empty())
+    }
+
+    /// The amount of processes in the queue
+    fn len(&self) -> usize {
+        self.levels.iter().map(VecDeque::len).sum()
+    }
+}
+
+impl Default for RunQueue {
+    fn default() -> Self {
+        RunQueue::new()
+    }
+}
+
+pub fn scheduler(hart_id: usize) -> ! {
+    ccpu().online.store(true, Ordering::SeqCst);
     loop {
+        // Let pending interrupts in, they can't come while a run queue is locked
         unsafe { s_enable() };
-        for proc_id in 0..NPROC {
-            let proc = proc(proc_id as ProcId);
-            if proc
-                .status
-                .compare_exchange(
-                    proc::ProcStatus::Runnable,
-                    proc::ProcStatus::Running,
-                    Ordering::SeqCst,
-                    Ordering::SeqCst,
-                )
-                .is_ok()
-            {
-                cprintln!(
-                    "CPU {} is running proc `{}` (id={})",
-                    cpuid(),
-                    proc.name(),
-                    proc_id,
-                );
-                ccpu().current_proc = proc.id;
-                unsafe {
-                    // sp::write(proc.kernel_stack as usize + STACK_SIZE);
-                    ra::write(user_proc_entry as usize);
-                    asm!("ret");
-                }
-            }
+        s_disable();
+        let next = ccpu().run_queue.lock().pop();
+        let Some(proc_id) = next.or_else(|| steal(hart_id)) else {
+            unsafe { s_enable() };
+            wfi();
+            continue;
+        };
+        let proc = proc(proc_id);
+        if !proc.allowed_on(hart_id) {
+            // The affinity of the process changed while it was queued here
+            enqueue(proc);
+            continue;
+        }
+        if proc
+            .status
+            .compare_exchange(
+                ProcStatus::Runnable,
+                ProcStatus::Running,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_err()
+        {
+            continue;
+        }
+        if cfg!(feature = "debug-sched") {
+            cprintln!(
+                "CPU {} is running proc `{}` (id={}, priority={})",
+                cpuid(),
+                proc.name(),
+                proc_id,
+                proc.priority(),
+            );
+        }
+        // The hart that ran the process last might still be switching away from it
+        while proc.on_cpu.load(Ordering::SeqCst) {
+            hint::spin_loop();
+        }
+        proc.on_cpu.store(true, Ordering::SeqCst);
+        proc.set_cpu(hart_id);
+        ccpu().current_proc = proc.id;
+        ccpu().running_proc = true;
+        unsafe { swtch(&mut ccpu().context, proc.context) };
+        // The process gave up the hart, we are back on the scheduler's stack
+        ccpu().running_proc = false;
+        proc.on_cpu.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Take a process that may run on the idle hart `hart_id` from the hart with the most queued
+/// processes. Only one run queue is locked at a time, so harts can steal from each other.
+fn steal(hart_id: usize) -> Option<ProcId> {
+    let (_, victim) = cpus()
+        .iter()
+        .enumerate()
+        .filter(|(id, cpu)| *id != hart_id && cpu.is_online())
+        .map(|(_, cpu)| (cpu.run_queue.lock().len(), cpu))
+        .filter(|(len, _)| *len > 0)
+        .max_by_key(|(len, _)| *len)?;
+    victim.run_queue.lock().pop_for(hart_id)
+}
+
+/// Queue a process that has just been made [`ProcStatus::Runnable`], on the level of its
+/// current priority. The process stays on the hart that ran it last if it may, otherwise it
+/// goes to the least loaded hart it may run on.
+pub fn enqueue(proc: &Process) {
+    let hart_id = proc
+        .cpu()
+        .filter(|id| proc.allowed_on(*id) && cpus()[*id].is_online())
+        .unwrap_or_else(|| least_loaded(proc));
+    proc.set_cpu(hart_id);
+    cpus()[hart_id].run_queue.lock().push(proc);
+}
+
+/// The online hart with the shortest run queue that the process may run on. While booting no
+/// hart is online yet, and the process is put on the current hart.
+fn least_loaded(proc: &Process) -> usize {
+    cpus()
+        .iter()
+        .enumerate()
+        .filter(|(id, cpu)| cpu.is_online() && proc.allowed_on(*id))
+        .min_by_key(|(_, cpu)| cpu.run_queue.lock().len())
+        .map_or(cpuid(), |(id, _)| id)
+}
+
+/// Is there a runnable process with a higher priority than `priority` waiting for the current
+/// hart
+pub fn has_higher_priority(priority: usize) -> bool {
+    ccpu()
+        .run_queue
+        .lock()
+        .highest_priority()
+        .is_some_and(|highest| highest < priority)
+}
+
+/// Change the highest priority level the process may run at, and move it to that level.
+pub fn set_nice(proc: &Process, nice: usize) {
+    for cpu in cpus() {
+        let mut run_queue = cpu.run_queue.lock();
+        if run_queue.remove(proc.id) {
+            proc.reset_priority(nice);
+            run_queue.push(proc);
+            return;
+        }
+    }
+    proc.reset_priority(nice);
+}
+
+/// Move the process off the run queue of a hart that it may not run on anymore, after its
+/// affinity changed. A running process moves once it gives up its hart.
+pub fn migrate(proc: &Process) {
+    for (id, cpu) in cpus().iter().enumerate() {
+        if !proc.allowed_on(id) && cpu.run_queue.lock().remove(proc.id) {
+            enqueue(proc);
+        }
+    }
+}
+
+/// The timer ticks since boot
+pub fn ticks() -> usize {
+    TICKS.load(Ordering::SeqCst)
+}
+
+/// Called on every timer interrupt. On every tick of hart 0 the sleeping processes check their
+/// deadlines, and every [`BOOST_INTERVAL`] ticks all of the processes are moved back to their
+/// highest priority level, so the processes that were demoted don't starve.
+pub fn clock_tick() {
+    if cpuid() != 0 {
+        return;
+    }
+    let ticks = TICKS.fetch_add(1, Ordering::SeqCst) + 1;
+    time::wake_sleepers();
+    if !ticks.is_multiple_of(BOOST_INTERVAL) {
+        return;
+    }
+    for proc in procs().iter() {
+        proc.reset_priority(proc.nice());
+    }
+    for cpu in cpus() {
+        let mut run_queue = cpu.run_queue.lock();
+        let mut queued = VecDeque::new();
+        for level in &mut run_queue.levels {
+            queued.append(level);
+        }
+        for proc_id in queued {
+            run_queue.push(proc(proc_id));
         }
-        wfi();
     }
 }
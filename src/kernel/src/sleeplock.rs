@@ -0,0 +1,74 @@
+use crate::{
+    cpu::{cproc, in_proc},
+    proc::wakeup,
+};
+use core::{
+    cell::UnsafeCell,
+    hint,
+    ops::{Deref, DerefMut},
+};
+use spin::Mutex;
+
+/// A lock that can be held for a long time, like across disk requests: a process that waits for
+/// it sleeps instead of spinning. Outside of a process (while booting) it spins.
+pub struct SleepLock<T> {
+    locked: Mutex<bool>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SleepLock<T> {}
+
+pub struct SleepLockGuard<'a, T> {
+    lock: &'a SleepLock<T>,
+}
+
+impl<T> SleepLock<T> {
+    pub const fn new(data: T) -> Self {
+        SleepLock {
+            locked: Mutex::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> SleepLockGuard<'_, T> {
+        let mut locked = self.locked.lock();
+        while *locked {
+            if in_proc() {
+                locked = cproc().sleep(self.chan(), &self.locked, locked);
+            } else {
+                drop(locked);
+                hint::spin_loop();
+                locked = self.locked.lock();
+            }
+        }
+        *locked = true;
+        SleepLockGuard { lock: self }
+    }
+
+    /// What the processes that wait for the lock sleep on
+    fn chan(&self) -> usize {
+        self as *const _ as usize
+    }
+}
+
+impl<T> Deref for SleepLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SleepLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SleepLockGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut locked = self.lock.locked.lock();
+        *locked = false;
+        wakeup(self.lock.chan());
+    }
+}
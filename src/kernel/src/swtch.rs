@@ -0,0 +1,46 @@
+use crate::proc::Context;
+
+/// Save the current callee-saved registers in `old`, load the ones in `new` and continue
+/// executing from `new.ra`, on the stack `new.sp`.
+/// The caller-saved registers are already saved on the stack by the compiler, since this is a
+/// regular function call from its point of view.
+///
+/// # Safety
+/// `old` must be valid for writes, and `new` must hold a context that was saved by `swtch` or
+/// set up to start a process, with a stack that's still alive.
+#[naked]
+#[no_mangle]
+pub unsafe extern "C" fn swtch(old: *mut Context, new: *const Context) {
+    core::arch::asm!(
+        "sd ra, 0(a0)",
+        "sd sp, 8(a0)",
+        "sd s0, 16(a0)",
+        "sd s1, 24(a0)",
+        "sd s2, 32(a0)",
+        "sd s3, 40(a0)",
+        "sd s4, 48(a0)",
+        "sd s5, 56(a0)",
+        "sd s6, 64(a0)",
+        "sd s7, 72(a0)",
+        "sd s8, 80(a0)",
+        "sd s9, 88(a0)",
+        "sd s10, 96(a0)",
+        "sd s11, 104(a0)",
+        "ld ra, 0(a1)",
+        "ld sp, 8(a1)",
+        "ld s0, 16(a1)",
+        "ld s1, 24(a1)",
+        "ld s2, 32(a1)",
+        "ld s3, 40(a1)",
+        "ld s4, 48(a1)",
+        "ld s5, 56(a1)",
+        "ld s6, 64(a1)",
+        "ld s7, 72(a1)",
+        "ld s8, 80(a1)",
+        "ld s9, 88(a1)",
+        "ld s10, 96(a1)",
+        "ld s11, 104(a1)",
+        "ret",
+        options(noreturn)
+    );
+}
@@ -1,62 +1,465 @@
-use core::{mem::transmute, slice, str};
-
-use fs::FileId;
+use alloc::vec::Vec;
+use core::{slice, sync::atomic::Ordering};
 
 use crate::{
-    cprint,
+    cprint, cprintln,
     cpu::cproc,
+    errno::{Errno, SysResult},
+    files::{
+        delete_file,
+        dir::EMPTY_DIR_ENTRY,
+        fd::{FileType, OpenFile, Stat},
+        make_dir, remove_dir, DirEntry,
+    },
     mem::{
-        paging::{translate, PageTableLevel},
-        virtual_mem::{PTEFlags, VirtAddr},
+        mmap,
+        paging::PageTable,
+        uaccess::{copy_bytes_from_user, copy_from_user, copy_str_from_user, copy_to_user},
     },
+    param::{ProcId, MAXARG, NPRIO, NPROC, PAGE_SIZE},
+    proc::{proc, procs, ProcStatus, Process},
+    scheduler,
+    time::{self, Timespec, CLOCK_MONOTONIC, NSEC_PER_SEC},
+};
+
+pub use numbers::*;
+
+/// The syscall numbers, shared with the `user` crate. A user program puts the number in `a6`.
+pub mod numbers {
+    pub const READ_SYSCALL: usize = 10;
+    pub const PRINT_SYSCALL: usize = 11;
+    pub const EXIT_SYSCALL: usize = 12;
+    pub const FORK_SYSCALL: usize = 13;
+    pub const EXEC_SYSCALL: usize = 14;
+    pub const WAIT_SYSCALL: usize = 15;
+    pub const NICE_SYSCALL: usize = 16;
+    pub const SETPRIORITY_SYSCALL: usize = 17;
+    pub const SCHED_SETAFFINITY_SYSCALL: usize = 18;
+    pub const GETPID_SYSCALL: usize = 19;
+    pub const GETPPID_SYSCALL: usize = 20;
+    pub const PS_SYSCALL: usize = 21;
+    pub const OPEN_SYSCALL: usize = 22;
+    pub const WRITE_SYSCALL: usize = 23;
+    pub const CLOSE_SYSCALL: usize = 24;
+    pub const LSEEK_SYSCALL: usize = 25;
+    pub const FSTAT_SYSCALL: usize = 26;
+    pub const BRK_SYSCALL: usize = 27;
+    pub const SBRK_SYSCALL: usize = 28;
+    pub const MMAP_SYSCALL: usize = 29;
+    pub const MUNMAP_SYSCALL: usize = 30;
+    pub const MPROTECT_SYSCALL: usize = 31;
+    pub const UPTIME_SYSCALL: usize = 32;
+    pub const CLOCK_GETTIME_SYSCALL: usize = 33;
+    pub const NANOSLEEP_SYSCALL: usize = 34;
+    pub const UNLINK_SYSCALL: usize = 35;
+    pub const FTRUNCATE_SYSCALL: usize = 36;
+    pub const MKDIR_SYSCALL: usize = 37;
+    pub const RMDIR_SYSCALL: usize = 38;
+    pub const READDIR_SYSCALL: usize = 39;
+    pub const CHDIR_SYSCALL: usize = 40;
+
+    /// One more than the largest syscall number
+    pub const NSYSCALL: usize = 41;
+}
+
+/// An entry of the syscall table
+#[derive(Clone, Copy)]
+pub struct Syscall {
+    pub name: &'static str,
+    /// How many of `a0`..`a5` the syscall takes, the handler gets exactly that many arguments
+    pub argc: usize,
+    /// Its result is encoded into `a0`, see [`Errno::encode`]
+    pub handler: fn(&[usize]) -> SysResult,
+}
+
+/// The syscalls, indexed by their number
+pub static SYSCALL_TABLE: [Option<Syscall>; NSYSCALL] = {
+    const fn entry(
+        name: &'static str,
+        argc: usize,
+        handler: fn(&[usize]) -> SysResult,
+    ) -> Option<Syscall> {
+        Some(Syscall {
+            name,
+            argc,
+            handler,
+        })
+    }
+    let mut table = [None; NSYSCALL];
+    table[READ_SYSCALL] = entry("read", 3, |a| read_syscall(a[0], a[1], a[2]));
+    table[PRINT_SYSCALL] = entry("print", 2, |a| print_syscall(a[0], a[1]));
+    table[EXIT_SYSCALL] = entry("exit", 1, |a| exit_syscall(a[0]));
+    table[FORK_SYSCALL] = entry("fork", 0, |_| fork_syscall());
+    table[EXEC_SYSCALL] = entry("exec", 4, |a| exec_syscall(a[0], a[1], a[2], a[3]));
+    table[WAIT_SYSCALL] = entry("wait", 1, |a| wait_syscall(a[0]));
+    table[NICE_SYSCALL] = entry("nice", 1, |a| nice_syscall(a[0] as isize));
+    table[SETPRIORITY_SYSCALL] = entry("setpriority", 2, |a| setpriority_syscall(a[0], a[1]));
+    table[SCHED_SETAFFINITY_SYSCALL] = entry("sched_setaffinity", 2, |a| {
+        sched_setaffinity_syscall(a[0], a[1])
+    });
+    table[GETPID_SYSCALL] = entry("getpid", 0, |_| getpid_syscall());
+    table[GETPPID_SYSCALL] = entry("getppid", 0, |_| getppid_syscall());
+    table[PS_SYSCALL] = entry("ps", 2, |a| ps_syscall(a[0], a[1]));
+    table[OPEN_SYSCALL] = entry("open", 3, |a| open_syscall(a[0], a[1], a[2]));
+    table[WRITE_SYSCALL] = entry("write", 3, |a| write_syscall(a[0], a[1], a[2]));
+    table[CLOSE_SYSCALL] = entry("close", 1, |a| close_syscall(a[0]));
+    table[LSEEK_SYSCALL] = entry("lseek", 3, |a| lseek_syscall(a[0], a[1] as isize, a[2]));
+    table[FSTAT_SYSCALL] = entry("fstat", 2, |a| fstat_syscall(a[0], a[1]));
+    table[BRK_SYSCALL] = entry("brk", 1, |a| brk_syscall(a[0]));
+    table[SBRK_SYSCALL] = entry("sbrk", 1, |a| sbrk_syscall(a[0] as isize));
+    table[MMAP_SYSCALL] = entry("mmap", 4, |a| mmap_syscall(a[0], a[1], a[2], a[3]));
+    table[MUNMAP_SYSCALL] = entry("munmap", 2, |a| munmap_syscall(a[0], a[1]));
+    table[MPROTECT_SYSCALL] = entry("mprotect", 3, |a| mprotect_syscall(a[0], a[1], a[2]));
+    table[UPTIME_SYSCALL] = entry("uptime", 0, |_| uptime_syscall());
+    table[CLOCK_GETTIME_SYSCALL] = entry("clock_gettime", 2, |a| clock_gettime_syscall(a[0], a[1]));
+    table[NANOSLEEP_SYSCALL] = entry("nanosleep", 1, |a| nanosleep_syscall(a[0]));
+    table[UNLINK_SYSCALL] = entry("unlink", 2, |a| unlink_syscall(a[0], a[1]));
+    table[FTRUNCATE_SYSCALL] = entry("ftruncate", 2, |a| ftruncate_syscall(a[0], a[1]));
+    table[MKDIR_SYSCALL] = entry("mkdir", 2, |a| mkdir_syscall(a[0], a[1]));
+    table[RMDIR_SYSCALL] = entry("rmdir", 2, |a| rmdir_syscall(a[0], a[1]));
+    table[READDIR_SYSCALL] = entry("readdir", 3, |a| readdir_syscall(a[0], a[1], a[2]));
+    table[CHDIR_SYSCALL] = entry("chdir", 2, |a| chdir_syscall(a[0], a[1]));
+    table
 };
 
-pub const READ_SYSCALL: usize = 10;
-pub const PRINT_SYSCALL: usize = 11;
-pub const EXIT_SYSCALL: usize = 12;
-
-pub unsafe fn syscall() {
-    let a0 = cproc().trapframe().a0;
-
-    let a1 = cproc().trapframe().a1;
-    let a2 = cproc().trapframe().a2;
-    let _a3 = cproc().trapframe().a3;
-    let _a4 = cproc().trapframe().a4;
-    let _a5 = cproc().trapframe().a5;
-    let a6 = cproc().trapframe().a6;
-
-    match a6 {
-        READ_SYSCALL => read_syscall(a0 as u16, slice::from_raw_parts_mut(transmute(a1), a2)),
-        PRINT_SYSCALL => {
-            let addr = translate(
-                cproc().pagetable(),
-                VirtAddr::from_raw(a0 as u64),
-                PageTableLevel::L2,
-                PTEFlags::valid().readable().userable(),
+/// Run the syscall whose number is in `a6`, with the arguments in `a0`..`a5`, and return its
+/// result in `a0`. Unknown syscalls return `ENOSYS`.
+pub fn syscall() {
+    let tf = cproc().trapframe();
+    let args = [tf.a0, tf.a1, tf.a2, tf.a3, tf.a4, tf.a5];
+    let res = match SYSCALL_TABLE.get(tf.a6).copied().flatten() {
+        Some(syscall) => (syscall.handler)(&args[..syscall.argc]),
+        None => {
+            cprintln!(
+                "Process `{}` (id={}) made an unknown syscall: {}",
+                cproc().name(),
+                cproc().id,
+                tf.a6
             );
-            // #[cfg(debug_assertions)]
-            // cprintln!(
-            //     "executing print syscall | addr={:#x}, len={}",
-            //     addr.as_u64(),
-            //     a1
-            // );
-            print_syscall(str::from_raw_parts(addr.as_u64() as *const u8, a1));
+            Err(Errno::ENOSYS)
+        }
+    };
+    unsafe { cproc().trapframe.as_mut().unwrap() }.a0 = Errno::encode(res);
+}
+
+/// Copy the arguments described by an array of `argc` `(ptr, len)` pairs at the user
+/// address `argv` into kernel buffers. Returns `E2BIG` if there are more than `MAXARG`.
+fn copy_args_from_user(argv: usize, argc: usize) -> Result<Vec<Vec<u8>>, Errno> {
+    if argc > MAXARG {
+        return Err(Errno::E2BIG);
+    }
+    let pt = cproc().pagetable();
+    let mut pairs = [(0usize, 0usize); MAXARG];
+    copy_from_user(
+        pt,
+        unsafe {
+            slice::from_raw_parts_mut(
+                pairs.as_mut_ptr() as *mut u8,
+                argc * size_of::<(usize, usize)>(),
+            )
+        },
+        argv,
+    )?;
+    pairs[..argc]
+        .iter()
+        .map(|(ptr, len)| copy_bytes_from_user(pt, *ptr, *len))
+        .collect()
+}
+
+/// Read up to `len` bytes from the file `fd` into the user buffer at `buf`.
+/// Returns how many bytes were read, 0 at the end of the file. Reading the console returns as
+/// soon as there is any input.
+pub fn read_syscall(fd: usize, buf: usize, len: usize) -> SysResult {
+    let file = cproc().fds().get(fd)?;
+    let console = file.stat()?.file_type == FileType::Console;
+    let mut chunk = [0u8; PAGE_SIZE];
+    let mut read = 0;
+    // Go through a kernel buffer a page at a time, `len` comes from the user
+    while read < len {
+        let want = (len - read).min(PAGE_SIZE);
+        let got = file.read(&mut chunk[..want])?;
+        copy_to_user(cproc().pagetable(), buf + read, &chunk[..got])?;
+        read += got;
+        // Another read of the console would wait for more keys
+        if got < want || console {
+            break;
         }
-        EXIT_SYSCALL => {
-            exit_syscall(a0);
+    }
+    Ok(read)
+}
+
+/// Write the `len` bytes of the user buffer at `buf` to the file `fd`.
+/// Returns how many bytes were written.
+pub fn write_syscall(fd: usize, buf: usize, len: usize) -> SysResult {
+    let file = cproc().fds().get(fd)?;
+    let mut chunk = [0u8; PAGE_SIZE];
+    let mut written = 0;
+    while written < len {
+        let want = (len - written).min(PAGE_SIZE);
+        copy_from_user(cproc().pagetable(), &mut chunk[..want], buf + written)?;
+        let put = file.write(&chunk[..want])?;
+        written += put;
+        if put < want {
+            break;
         }
-        syscall => panic!("Unrecognized Syscall: {syscall}"),
     }
+    Ok(written)
 }
 
-pub fn read_syscall(_file_id: FileId, _buff: &mut [u8]) {
-    todo!()
+/// Open the file at the path of `path_len` bytes at `path`, with the access mode and the
+/// `O_*` flags in `flags`. Returns the new file descriptor.
+pub fn open_syscall(path: usize, path_len: usize, flags: usize) -> SysResult {
+    let path = copy_str_from_user(cproc().pagetable(), path, path_len)?;
+    let file = OpenFile::open(cproc().cwd(), &path, flags)?;
+    cproc().fds().alloc(file)
 }
 
-pub fn print_syscall(to_print: &str) {
-    cprint!("{}", to_print);
+pub fn close_syscall(fd: usize) -> SysResult {
+    cproc().fds().close(fd)?;
+    Ok(0)
 }
 
-pub fn exit_syscall(exit_code: usize) {
+/// Move the offset of the file `fd` to `offset` bytes from `whence`. Returns the new offset.
+pub fn lseek_syscall(fd: usize, offset: isize, whence: usize) -> SysResult {
+    cproc().fds().get(fd)?.seek(offset, whence)
+}
+
+/// Copy the [`Stat`] of the file `fd` to the user address `buf`.
+pub fn fstat_syscall(fd: usize, buf: usize) -> SysResult {
+    let stat = cproc().fds().get(fd)?.stat()?;
+    copy_to_user(cproc().pagetable(), buf, unsafe {
+        slice::from_raw_parts(&stat as *const Stat as *const u8, size_of::<Stat>())
+    })?;
+    Ok(0)
+}
+
+/// Delete the file at the path of `path_len` bytes at `path` from the disk.
+pub fn unlink_syscall(path: usize, path_len: usize) -> SysResult {
+    let path = copy_str_from_user(cproc().pagetable(), path, path_len)?;
+    delete_file(cproc().cwd(), &path)?;
+    Ok(0)
+}
+
+/// Change the size of the file `fd` to `size` bytes.
+pub fn ftruncate_syscall(fd: usize, size: usize) -> SysResult {
+    cproc().fds().get(fd)?.truncate(size)?;
+    Ok(0)
+}
+
+/// Create an empty directory at the path of `path_len` bytes at `path`.
+pub fn mkdir_syscall(path: usize, path_len: usize) -> SysResult {
+    let path = copy_str_from_user(cproc().pagetable(), path, path_len)?;
+    make_dir(cproc().cwd(), &path)?;
+    Ok(0)
+}
+
+/// Delete the empty directory at the path of `path_len` bytes at `path`.
+pub fn rmdir_syscall(path: usize, path_len: usize) -> SysResult {
+    let path = copy_str_from_user(cproc().pagetable(), path, path_len)?;
+    remove_dir(cproc().cwd(), &path)?;
+    Ok(0)
+}
+
+/// Read up to `count` entries of the directory `fd` into the array of [`DirEntry`] at the user
+/// address `buf`. Returns how many entries were read, 0 once there are no more.
+pub fn readdir_syscall(fd: usize, buf: usize, count: usize) -> SysResult {
+    let file = cproc().fds().get(fd)?;
+    let mut entries = [EMPTY_DIR_ENTRY; 16];
+    let read = file.read_dir(&mut entries[..count.min(16)])?;
+    copy_to_user(cproc().pagetable(), buf, unsafe {
+        slice::from_raw_parts(entries.as_ptr() as *const u8, read * size_of::<DirEntry>())
+    })?;
+    Ok(read)
+}
+
+/// Change the working directory to the path of `path_len` bytes at `path`.
+pub fn chdir_syscall(path: usize, path_len: usize) -> SysResult {
+    let path = copy_str_from_user(cproc().pagetable(), path, path_len)?;
+    cproc().set_cwd(&path)?;
+    Ok(0)
+}
+
+/// Print the string of `len` bytes at the user address `ptr` to the console.
+pub fn print_syscall(ptr: usize, len: usize) -> SysResult {
+    cprint!("{}", copy_str_from_user(cproc().pagetable(), ptr, len)?);
+    Ok(len)
+}
+
+pub fn exit_syscall(exit_code: usize) -> SysResult {
     cproc().exit(exit_code);
 }
+
+/// Returns the id of the child to the parent, or `EAGAIN` if the process couldn't be forked.
+pub fn fork_syscall() -> SysResult {
+    cproc().fork().map(|pid| pid as usize).ok_or(Errno::EAGAIN)
+}
+
+/// Run the program at the path of `path_len` bytes at `path`, with the `argc` arguments
+/// described by the array of `(ptr, len)` pairs at `argv`. Only returns to the caller if the
+/// program couldn't be started. Otherwise the new program gets the amount of arguments in `a0`.
+pub fn exec_syscall(path: usize, path_len: usize, argv: usize, argc: usize) -> SysResult {
+    let path = copy_str_from_user(cproc().pagetable(), path, path_len)?;
+    let args = copy_args_from_user(argv, argc)?;
+    cproc().exec(&path, &args)?;
+    Ok(args.len())
+}
+
+/// Wait for the child `pid` to exit, or for any child if `pid` is `usize::MAX`.
+/// Returns the id of the child, with its exit code in `a1`, or `ECHILD` if there is no such
+/// child.
+pub fn wait_syscall(pid: usize) -> SysResult {
+    let proc = cproc();
+    let pid = match pid {
+        usize::MAX => None,
+        pid if pid < NPROC => Some(pid as ProcId),
+        _ => return Err(Errno::ECHILD),
+    };
+    let (child, exit_code) = proc.wait(pid).ok_or(Errno::ECHILD)?;
+    unsafe { proc.trapframe.as_mut().unwrap() }.a1 = exit_code;
+    Ok(child as usize)
+}
+
+/// Add `inc` to the nice value of the process, clamped to the priority levels.
+/// Returns the new nice value.
+pub fn nice_syscall(inc: isize) -> SysResult {
+    let proc = cproc();
+    let nice = (proc.nice() as isize)
+        .saturating_add(inc)
+        .clamp(0, NPRIO as isize - 1) as usize;
+    proc.set_nice(nice)?;
+    Ok(nice)
+}
+
+/// Set the nice value of the process `pid`, or of the calling process if `pid` is `usize::MAX`.
+/// Returns `ESRCH` if there is no such process, or `EINVAL` if `nice` isn't a priority level.
+pub fn setpriority_syscall(pid: usize, nice: usize) -> SysResult {
+    target_proc(pid)?.set_nice(nice)?;
+    Ok(0)
+}
+
+/// Pin the process `pid` (or the calling process if `pid` is `usize::MAX`) to the harts that
+/// have their bit set in `mask`. Returns `ESRCH` if there is no such process, or `EINVAL` if
+/// none of the harts in `mask` is online.
+pub fn sched_setaffinity_syscall(pid: usize, mask: usize) -> SysResult {
+    target_proc(pid)?.set_affinity(mask)?;
+    Ok(0)
+}
+
+/// The live process `pid` refers to, where `usize::MAX` is the calling process
+fn target_proc<'a>(pid: usize) -> Result<&'a Process, Errno> {
+    match pid {
+        usize::MAX => Some(cproc()),
+        pid if pid < NPROC => Some(proc(pid as ProcId)),
+        _ => None,
+    }
+    .filter(|target| {
+        !matches!(
+            target.status.load(Ordering::SeqCst),
+            ProcStatus::Unused | ProcStatus::Zombie
+        )
+    })
+    .ok_or(Errno::ESRCH)
+}
+
+/// Returns the id of the calling process.
+pub fn getpid_syscall() -> SysResult {
+    Ok(cproc().id as usize)
+}
+
+/// Returns the id of the parent of the calling process.
+pub fn getppid_syscall() -> SysResult {
+    Ok(cproc().parent() as usize)
+}
+
+/// Copy a [`ProcInfo`](crate::proc::ProcInfo) for each process that is in use (but at most
+/// `cap`) to the array at the user address `buf`. Returns the amount of processes that were
+/// copied.
+pub fn ps_syscall(buf: usize, cap: usize) -> SysResult {
+    let snapshot = procs().snapshot();
+    let infos = &snapshot[..snapshot.len().min(cap)];
+    copy_to_user(cproc().pagetable(), buf, unsafe {
+        slice::from_raw_parts(infos.as_ptr() as *const u8, size_of_val(infos))
+    })?;
+    Ok(infos.len())
+}
+
+/// Move the end of the heap of the process to `addr`, or only return it if `addr` is 0.
+/// Returns the new end of the heap, or `ENOMEM` if it couldn't be moved.
+pub fn brk_syscall(addr: usize) -> SysResult {
+    let proc = cproc();
+    if addr == 0 {
+        return Ok(proc.brk());
+    }
+    proc.set_brk(addr)
+}
+
+/// Grow the heap of the process by `inc` bytes, or shrink it if `inc` is negative.
+/// Returns the old end of the heap, which is where the new memory starts.
+pub fn sbrk_syscall(inc: isize) -> SysResult {
+    let proc = cproc();
+    let old_brk = proc.brk();
+    proc.set_brk(old_brk.checked_add_signed(inc).ok_or(Errno::ENOMEM)?)?;
+    Ok(old_brk)
+}
+
+/// The page table of the calling process, for the syscalls that change its mappings
+fn cproc_pagetable<'a>() -> &'a mut PageTable {
+    unsafe { cproc().page_table.as_mut().unwrap() }
+}
+
+/// Map `len` bytes of zeroed memory with the protection `prot`, near `hint` if possible.
+/// Returns the address of the new memory.
+pub fn mmap_syscall(hint: usize, len: usize, prot: usize, flags: usize) -> SysResult {
+    mmap::mmap(cproc_pagetable(), hint, len, prot, flags)
+}
+
+/// Unmap the pages of `addr..addr + len`, which must be in the mmap region.
+pub fn munmap_syscall(addr: usize, len: usize) -> SysResult {
+    mmap::munmap(cproc_pagetable(), addr, len)?;
+    Ok(0)
+}
+
+/// Change the protection of the pages of `addr..addr + len` to `prot`.
+pub fn mprotect_syscall(addr: usize, len: usize, prot: usize) -> SysResult {
+    mmap::mprotect(cproc_pagetable(), addr, len, prot)?;
+    Ok(0)
+}
+
+/// Returns the timer ticks since boot.
+pub fn uptime_syscall() -> SysResult {
+    Ok(scheduler::ticks())
+}
+
+/// Copy the current time of the clock `clock_id` to the [`Timespec`] at the user address `tp`.
+/// Only [`CLOCK_MONOTONIC`] is supported, other clocks return `EINVAL`.
+pub fn clock_gettime_syscall(clock_id: usize, tp: usize) -> SysResult {
+    if clock_id != CLOCK_MONOTONIC {
+        return Err(Errno::EINVAL);
+    }
+    let now = Timespec::from_nanos(time::now_nanos());
+    copy_to_user(cproc().pagetable(), tp, unsafe {
+        slice::from_raw_parts(&now as *const Timespec as *const u8, size_of::<Timespec>())
+    })?;
+    Ok(0)
+}
+
+/// Sleep for the duration in the [`Timespec`] at the user address `req`. Returns `EINVAL` if
+/// its nanoseconds aren't below a second.
+pub fn nanosleep_syscall(req: usize) -> SysResult {
+    let mut duration = Timespec::default();
+    copy_from_user(
+        cproc().pagetable(),
+        unsafe {
+            slice::from_raw_parts_mut(
+                &mut duration as *mut Timespec as *mut u8,
+                size_of::<Timespec>(),
+            )
+        },
+        req,
+    )?;
+    if duration.tv_nsec >= NSEC_PER_SEC {
+        return Err(Errno::EINVAL);
+    }
+    time::sleep_until(time::now_nanos().saturating_add(duration.as_nanos()));
+    Ok(0)
+}
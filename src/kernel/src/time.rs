@@ -0,0 +1,61 @@
+use crate::{arch::clint::mtime, cpu::cproc, memlayout::MTIME_FREQ, proc::wakeup};
+use spin::Mutex;
+
+/// The clock `clock_gettime` supports: the time since boot, which never goes back
+pub const CLOCK_MONOTONIC: usize = 1;
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// A point in time, or a duration, as `clock_gettime` and `nanosleep` take it from user memory
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(C)]
+pub struct Timespec {
+    pub tv_sec: u64,
+    /// Always below [`NSEC_PER_SEC`]
+    pub tv_nsec: u64,
+}
+
+impl Timespec {
+    pub fn from_nanos(nanos: u64) -> Self {
+        Timespec {
+            tv_sec: nanos / NSEC_PER_SEC,
+            tv_nsec: nanos % NSEC_PER_SEC,
+        }
+    }
+
+    pub fn as_nanos(&self) -> u64 {
+        self.tv_sec
+            .saturating_mul(NSEC_PER_SEC)
+            .saturating_add(self.tv_nsec)
+    }
+}
+
+/// Held while checking whether a sleeping process should wake up, so the check and the sleep
+/// can't miss a tick in between
+static SLEEP_LOCK: Mutex<()> = Mutex::new(());
+
+/// What processes that sleep for a while wait on
+fn sleep_chan() -> usize {
+    &SLEEP_LOCK as *const _ as usize
+}
+
+/// The nanoseconds since boot, `mtime` starts at 0 when the machine is reset
+pub fn now_nanos() -> u64 {
+    mtime::read() as u64 * (NSEC_PER_SEC / MTIME_FREQ as u64)
+}
+
+/// Block the current process until [`now_nanos`] reaches `deadline`. The deadline is checked on
+/// every timer tick, so the process can oversleep by up to a tick.
+pub fn sleep_until(deadline: u64) {
+    let proc = cproc();
+    let mut guard = SLEEP_LOCK.lock();
+    while now_nanos() < deadline {
+        guard = proc.sleep(sleep_chan(), &SLEEP_LOCK, guard);
+    }
+}
+
+/// Let the sleeping processes check their deadlines, called on every timer tick.
+pub fn wake_sleepers() {
+    let _guard = SLEEP_LOCK.lock();
+    wakeup(sleep_chan());
+}
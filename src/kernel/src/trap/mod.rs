@@ -10,6 +10,7 @@ use crate::mem::virtual_mem::{PTEFlags, VirtAddr};
 use crate::memlayout::TRAMPOLINE_VADDR;
 use crate::param::STACK_SIZE;
 use crate::proc::ProcStatus;
+use crate::scheduler::clock_tick;
 use crate::syscall::syscall;
 use crate::trampoline::trampoline;
 use crate::{
@@ -40,22 +41,24 @@ extern "C" {
 pub fn user_proc_entry() {
     s_disable();
     let proc = cproc();
-    cprintln!(
-        "Address for entry point for new process `{}` (id={}) in Kernel Ram: {:#x}",
-        proc.name(),
-        proc.id,
-        translate(
-            proc.pagetable(),
-            VirtAddr::from_raw(0x1000),
-            crate::mem::paging::PageTableLevel::L2,
-            PTEFlags::valid()
-                .executable()
-                .readable()
-                .writable()
-                .userable(),
-        )
-        .as_u64()
-    );
+    if cfg!(feature = "debug-sched") {
+        cprintln!(
+            "Address for entry point for new process `{}` (id={}) in Kernel Ram: {:#x}",
+            proc.name(),
+            proc.id,
+            translate(
+                proc.pagetable(),
+                VirtAddr::from_raw(0x1000),
+                crate::mem::paging::PageTableLevel::L2,
+                PTEFlags::valid()
+                    .executable()
+                    .readable()
+                    .writable()
+                    .userable(),
+            )
+            .as_u64()
+        );
+    }
     user_trap_return();
 }
 
@@ -116,9 +119,14 @@ pub unsafe extern "C" fn usertrap() {
         scause::Trap::Interrupt(int) => match int {
             Interrupt::SupervisorExternal => device_interrupt(hart_id),
             Interrupt::SupervisorSoft => {
+                // The timer raised a software interrupt
                 let sip: usize;
                 asm!("csrr {x}, sip", x = out(reg) sip);
                 asm!("csrw sip, {x}", x = in(reg) (sip & !2));
+                clock_tick();
+                if cproc().tick() {
+                    cproc().yield_cpu();
+                }
             }
             int => {
                 panic!("Unrecognized interrupt: {:#?}", int)
@@ -156,6 +164,7 @@ pub unsafe extern "C" fn kerneltrap() {
                 let sip: usize;
                 asm!("csrr {x}, sip", x = out(reg) sip);
                 asm!("csrw sip, {x}", x = in(reg) (sip & !2));
+                clock_tick();
             }
             int => {
                 panic!("Unrecognized interrupt: {:#?}", int)
@@ -1,6 +1,11 @@
 use core::ascii;
 
-use crate::{memlayout::UART_BASE_ADDR, Console, CONSOLE};
+use crate::{
+    keyboard::{self, KEYBOARD},
+    memlayout::UART_BASE_ADDR,
+    proc::wakeup,
+    Console, CONSOLE,
+};
 use spin::Mutex;
 
 /// Uart 16550
@@ -107,18 +112,29 @@ impl Uart {
     }
 }
 
+/// Move the received bytes into the keyboard input queue, echo them to the console, and wake up
+/// the processes that are waiting for input.
 pub fn uart_interrupt() {
     let mut console = CONSOLE.lock();
     let mut uart = UART.lock();
-    // let mut kb = KEYBOARD.lock();
+    let mut kb = KEYBOARD.lock();
 
+    let mut received = false;
     while let Some(key) = unsafe { uart.get_next() } {
-        console
-            .write_char(ascii::Char::from_u8(key).unwrap())
-            .unwrap();
-        console
-            .write_char(ascii::Char::from_u8(key).unwrap())
-            .unwrap();
+        // Terminals send a carriage return for the enter key
+        let key = if key == b'\r' { b'\n' } else { key };
+        if key == keyboard::BACKSPACE {
+            // Erase the last character on the screen
+            console.write_str("\x08 \x08");
+        } else if let Some(c) = ascii::Char::from_u8(key) {
+            let _ = console.write_char(c);
+        }
+        // A full queue drops the key
+        let _ = kb.update_new_press(key);
+        received = true;
     }
     uart.async_send_pending(&mut *console);
+    if received {
+        wakeup(keyboard::input_chan());
+    }
 }
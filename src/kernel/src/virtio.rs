@@ -1,7 +1,11 @@
-use crate::memlayout::VIRTIO0;
+use crate::{
+    cpu::{cproc, in_proc},
+    memlayout::VIRTIO0,
+    proc::wakeup,
+};
 use alloc::boxed::Box;
 use conquer_once::spin::OnceCell;
-use core::{mem::MaybeUninit, sync::atomic::fence};
+use core::{hint, mem::MaybeUninit, sync::atomic::fence};
 use spin::Mutex;
 
 //
@@ -84,7 +88,7 @@ pub const VIRTIO_BLK_F_RING_EVENT_IDX: u32 = 1 << 29;
 /// Read data from the disk into the buffer
 pub const VIRTIO_BLK_T_IN: u32 = 0;
 /// Write data from the buffer into the disk
-pub const VIRTIO_BLK_T_OUT: u32 = 0;
+pub const VIRTIO_BLK_T_OUT: u32 = 1;
 
 // Status values for block device requests -- Section 5.2.6 of the spec
 
@@ -104,7 +108,9 @@ pub struct VirtioDisk {
     /// Indexed by idx % VIRTQ_CAP, is the desc free to use?
     free_desc: [bool; VIRTQ_CAP],
     req_placeholder: [MaybeUninit<VirtioBlkReq>; VIRTQ_CAP],
-    // statuses: [u8; VIRTQ_CAP],
+    /// Indexed by the head desc of a request, the device writes the status of the request here.
+    /// The address of the status is also the channel that the requesting process sleeps on.
+    statuses: [u8; VIRTQ_CAP],
     used_idxs: u16,
 }
 
@@ -249,7 +255,7 @@ pub fn init_virtio() {
                 used_ring,
                 free_desc: [true; VIRTQ_CAP],
                 req_placeholder: [MaybeUninit::zeroed(); VIRTQ_CAP],
-                // statuses: [0; VIRTQ_CAP],
+                statuses: [0; VIRTQ_CAP],
                 used_idxs: 0,
             })
         });
@@ -284,21 +290,33 @@ impl VirtioDisk {
     }
 }
 
+/// Read a 1KB block starting at `sector`. When called from a process, the process sleeps until
+/// the disk is done, otherwise (while booting) this spins.
 pub fn read_from_disk(sector: u64, data: &mut [u8; 1024]) -> Result<(), u8> {
-    let status: u8 = 0xff;
-    let status_addr: u64 = (&status) as *const _ as u64;
+    disk_request(sector, data.as_mut_ptr(), VIRTIO_BLK_T_IN)
+}
+
+/// Write a 1KB block starting at `sector`, see [`read_from_disk`].
+pub fn write_to_disk(sector: u64, data: &[u8; 1024]) -> Result<(), u8> {
+    disk_request(sector, data.as_ptr() as *mut u8, VIRTIO_BLK_T_OUT)
+}
+
+/// Send a request of type `ty` for the 1KB block at `sector` with the buffer at `data`, and
+/// wait for it to finish. The device only writes to the buffer for [`VIRTIO_BLK_T_IN`].
+fn disk_request(sector: u64, data: *mut u8, ty: u32) -> Result<(), u8> {
+    let disk_lock = DISK.get().unwrap();
+    let mut disk = disk_lock.lock();
     let head_desc_chain = {
-        let mut disk = DISK.get().unwrap().lock();
         let desc_id1 = disk.alloc_desc().ok_or(0)?;
         let desc_id2 = disk.alloc_desc().ok_or(0)?;
         let desc_id3 = disk.alloc_desc().ok_or(0)?;
 
-        // disk.statuses[desc_id1 as usize] = 0xff;
-        // let status_addr: u64 = (&disk.statuses[desc_id1 as usize]) as *const _ as u64;
+        disk.statuses[desc_id1 as usize] = 0xff;
+        let status_addr: u64 = (&disk.statuses[desc_id1 as usize]) as *const _ as u64;
 
         let req = &mut disk.req_placeholder[desc_id1 as usize];
         let req_addr = req.write(VirtioBlkReq {
-            ty: VIRTIO_BLK_T_IN,
+            ty,
             _reserved: 0,
             sector,
         }) as *mut _ as u64;
@@ -310,11 +328,16 @@ pub fn read_from_disk(sector: u64, data: &mut [u8; 1024]) -> Result<(), u8> {
         desc1.flags = VIRTQ_DESC_F_NEXT;
         desc1.next = desc_id2;
 
-        // The second descriptor - this descriptor defines the data buffer
+        // The second descriptor - this descriptor defines the data buffer, which is
+        // device-writable only if the device reads into it
         let desc2 = &mut disk.desc_table[desc_id2 as usize];
-        desc2.addr = data as *mut _ as u64;
-        desc2.len = data.len() as u32;
-        desc2.flags = VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE;
+        desc2.addr = data as u64;
+        desc2.len = 1024;
+        desc2.flags = if ty == VIRTIO_BLK_T_IN {
+            VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE
+        } else {
+            VIRTQ_DESC_F_NEXT
+        };
         desc2.next = desc_id3;
 
         // The third buffer - this descriptor defines a 1 byte buffer that the device
@@ -329,7 +352,7 @@ pub fn read_from_disk(sector: u64, data: &mut [u8; 1024]) -> Result<(), u8> {
 
         fence(core::sync::atomic::Ordering::SeqCst);
 
-        disk.avail_ring.idx += 1;
+        disk.avail_ring.idx = disk.avail_ring.idx.wrapping_add(1);
 
         fence(core::sync::atomic::Ordering::SeqCst);
 
@@ -339,10 +362,19 @@ pub fn read_from_disk(sector: u64, data: &mut [u8; 1024]) -> Result<(), u8> {
     };
 
     loop {
-        match status {
-            0xff => continue,
+        let status_ptr = &disk.statuses[head_desc_chain as usize] as *const u8;
+        match unsafe { status_ptr.read_volatile() } {
+            0xff => {
+                if in_proc() {
+                    // `virtio_intr` wakes us up once the request is done
+                    disk = cproc().sleep(status_ptr as usize, disk_lock, disk);
+                } else {
+                    drop(disk);
+                    hint::spin_loop();
+                    disk = disk_lock.lock();
+                }
+            }
             s => {
-                let mut disk = DISK.get().unwrap().lock();
                 disk.free_desc_chain(head_desc_chain);
 
                 if s != 0 {
@@ -365,16 +397,12 @@ pub fn virtio_intr() {
     fence(core::sync::atomic::Ordering::SeqCst);
 
     let mut current_idx = disk.used_idxs;
-    while current_idx < disk.used_ring.idx {
+    while current_idx != disk.used_ring.idx {
         fence(core::sync::atomic::Ordering::SeqCst);
-        // let head_desc_id = disk.used_ring.ring[current_idx as usize % VIRTQ_CAP].desc_id as u16;
-        // match disk.statuses[head_desc_id as usize] {
-        //     0 => {}
-        //     err => panic!("Disk operation resulted in error - Status: {}", err),
-        // }
-        // disk.free_desc_chain(head_desc_id);
-        //
-        current_idx += 1;
+        let head_desc_id = disk.used_ring.ring[current_idx as usize % VIRTQ_CAP].desc_id as usize;
+        // The process that made the request sleeps on its status
+        wakeup(&disk.statuses[head_desc_id] as *const u8 as usize);
+        current_idx = current_idx.wrapping_add(1);
     }
     disk.used_idxs = disk.used_ring.idx;
 }
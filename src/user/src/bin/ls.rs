@@ -1,10 +1,39 @@
 #![no_std]
 #![no_main]
 
-// use fs::FILES;
-use kernel::*;
+use user::{
+    eprintln, println,
+    rt::args,
+    syscalls::{close, open, readdir, EMPTY_DIR_ENTRY, O_RDONLY},
+};
 
 #[no_mangle]
-fn maine() {
-    cprintln!("Hi");
+fn main() -> i32 {
+    let dir = args().nth(1).unwrap_or(".");
+    let fd = match open(dir, O_RDONLY) {
+        Ok(fd) => fd,
+        Err(errno) => {
+            eprintln!("ls: {}: {:?}", dir, errno);
+            return 1;
+        }
+    };
+    let mut entries = [EMPTY_DIR_ENTRY; 16];
+    let mut code = 0;
+    loop {
+        match readdir(fd, &mut entries) {
+            Ok(0) => break,
+            Ok(read) => {
+                for entry in &entries[..read] {
+                    println!("{}", entry.name());
+                }
+            }
+            Err(errno) => {
+                eprintln!("ls: {}: {:?}", dir, errno);
+                code = 1;
+                break;
+            }
+        }
+    }
+    let _ = close(fd);
+    code
 }
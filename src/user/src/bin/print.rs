@@ -1,10 +1,10 @@
 #![no_std]
 #![no_main]
 
-use user::syscalls::{exit, print};
+use user::println;
 
 #[no_mangle]
-fn maine() {
-    print("Hi! (From User Mode)\n");
-    exit(1);
+fn main() -> i32 {
+    println!("Hi! (From User Mode)");
+    1
 }
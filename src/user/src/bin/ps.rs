@@ -0,0 +1,30 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user::{
+    eprintln, println,
+    syscalls::{ps, ProcInfo, NPROC},
+};
+
+#[no_mangle]
+fn main() -> i32 {
+    let mut procs = Vec::<ProcInfo>::with_capacity(NPROC);
+    let procs = match ps(procs.spare_capacity_mut()) {
+        Ok(procs) => procs,
+        Err(errno) => {
+            eprintln!("ps: {:?}", errno);
+            return 1;
+        }
+    };
+    println!("PID\tPPID\tSTATUS\t\tTICKS\tNAME");
+    for proc in procs {
+        println!(
+            "{}\t{}\t{:?}\t{}\t{}",
+            proc.pid, proc.parent, proc.status, proc.cpu_ticks, proc.name
+        );
+    }
+    0
+}
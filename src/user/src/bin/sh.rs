@@ -0,0 +1,99 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{format, string::String, vec::Vec};
+use user::{
+    eprintln,
+    io::{self, read_line},
+    print, println,
+    syscalls::{chdir, exec, exit, fork, waitpid},
+};
+
+const PROMPT: &str = "$ ";
+
+/// The exit code of a child that couldn't run the program, like in other shells
+const EXEC_FAILED_EXIT_CODE: usize = 127;
+
+#[no_mangle]
+fn main() -> i32 {
+    let mut line = String::new();
+    loop {
+        print!("{}", PROMPT);
+        io::flush();
+        line.clear();
+        match read_line(&mut line) {
+            Ok(true) => {}
+            Ok(false) => return 0,
+            Err(errno) => {
+                eprintln!("sh: read: {:?}", errno);
+                return 1;
+            }
+        }
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, rest)) = args.split_first() else {
+            continue;
+        };
+        match command {
+            "exit" => return exit_code(rest),
+            "help" => help(),
+            "cd" => cd(rest),
+            _ => run(&args),
+        }
+    }
+}
+
+/// The exit code `exit` was given, 0 if it wasn't given one
+fn exit_code(args: &[&str]) -> i32 {
+    match args {
+        [] => 0,
+        [code, ..] => code.parse().unwrap_or_else(|_| {
+            eprintln!("exit: {}: not a number", code);
+            1
+        }),
+    }
+}
+
+fn help() {
+    println!("Built-in commands:");
+    println!("  cd [DIR]     change the working directory, to / if DIR isn't given");
+    println!("  exit [CODE]  leave the shell");
+    println!("  help         show this message");
+    println!("Anything else runs the program of that name from the root directory, with the given");
+    println!("arguments. A name with a / in it is a path to the program.");
+}
+
+fn cd(args: &[&str]) {
+    let dir = match args {
+        [] => "/",
+        [dir] => dir,
+        _ => return eprintln!("cd: too many arguments"),
+    };
+    if let Err(errno) = chdir(dir) {
+        eprintln!("cd: {}: {:?}", dir, errno);
+    }
+}
+
+/// Run the program `args[0]` with `args` in a child process, and wait for it to exit. Programs
+/// are looked up in the root directory, unless `args[0]` is a path.
+fn run(args: &[&str]) {
+    let path = if args[0].contains('/') {
+        String::from(args[0])
+    } else {
+        format!("/{}", args[0])
+    };
+    match fork() {
+        Ok(0) => {
+            let errno = exec(&path, args);
+            eprintln!("sh: {}: {:?}", args[0], errno);
+            exit(EXEC_FAILED_EXIT_CODE);
+        }
+        Ok(child) => match waitpid(child) {
+            Ok(0) => {}
+            Ok(code) => println!("[{} exited with code {}]", args[0], code),
+            Err(errno) => eprintln!("sh: wait: {:?}", errno),
+        },
+        Err(errno) => eprintln!("sh: fork: {:?}", errno),
+    }
+}
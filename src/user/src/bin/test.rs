@@ -1,10 +1,12 @@
 #![no_std]
 #![no_main]
 
-use core::{arch::asm, panic::PanicInfo};
+use core::arch::asm;
+// The runtime, for `_start` and the panic handler
+use user as _;
 
 #[no_mangle]
-fn maine() {
+fn main() -> i32 {
     let mut a = 0;
     unsafe {
         asm!("ecall");
@@ -15,8 +17,3 @@ fn maine() {
         }
     }
 }
-
-#[panic_handler]
-fn panic(_: &PanicInfo) -> ! {
-    loop {}
-}
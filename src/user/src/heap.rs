@@ -0,0 +1,48 @@
+//! The heap of user programs. It starts out empty, and grows with `sbrk` whenever an allocation
+//! doesn't fit.
+
+use crate::syscalls::sbrk;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::{self, NonNull},
+};
+use kernel::param::PAGE_SIZE;
+use linked_list_allocator::LockedHeap;
+
+/// The least the heap grows by, so small allocations don't each need a syscall
+const MIN_GROWTH: usize = 4 * PAGE_SIZE;
+
+#[global_allocator]
+static HEAP: UserHeap = UserHeap(LockedHeap::empty());
+
+pub struct UserHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for UserHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        if let Ok(ptr) = heap.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+        // Grow by enough to fit the allocation at any alignment
+        let growth = (layout.size() + layout.align())
+            .max(MIN_GROWTH)
+            .next_multiple_of(PAGE_SIZE);
+        let Ok(start) = sbrk(growth as isize) else {
+            return ptr::null_mut();
+        };
+        // The kernel hands out the heap in one piece, so the new memory continues the old
+        if heap.size() == 0 {
+            heap.init(start, growth);
+        } else {
+            heap.extend(growth);
+        }
+        heap.allocate_first_fit(layout)
+            .map_or(ptr::null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0
+            .lock()
+            .deallocate(NonNull::new_unchecked(ptr), layout);
+    }
+}
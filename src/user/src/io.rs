@@ -0,0 +1,139 @@
+//! Console input and formatted output for user programs, see [`read_line`],
+//! [`print!`](crate::print) and [`eprint!`](crate::eprint). Standard output is line buffered,
+//! standard error isn't buffered.
+
+use crate::syscalls::{read, write, Errno, STDERR, STDIN, STDOUT};
+use alloc::{string::String, vec::Vec};
+use core::fmt::{self, Write};
+pub use kernel::keyboard::BACKSPACE;
+use spin::Mutex;
+
+/// The size of the line buffer of stdout, longer lines are written in pieces
+const STDOUT_BUF_SIZE: usize = 256;
+
+static STDOUT_WRITER: Mutex<Stdout> = Mutex::new(Stdout {
+    buf: [0; STDOUT_BUF_SIZE],
+    len: 0,
+});
+
+/// Write all of `bytes` to the file `fd`
+fn write_all(fd: usize, mut bytes: &[u8]) -> fmt::Result {
+    while !bytes.is_empty() {
+        match write(fd, bytes) {
+            Ok(0) | Err(_) => return Err(fmt::Error),
+            Ok(written) => bytes = &bytes[written..],
+        }
+    }
+    Ok(())
+}
+
+/// Standard output, which is flushed at every newline and when the buffer fills up
+pub struct Stdout {
+    buf: [u8; STDOUT_BUF_SIZE],
+    len: usize,
+}
+
+impl Stdout {
+    pub fn flush(&mut self) -> fmt::Result {
+        let res = write_all(STDOUT, &self.buf[..self.len]);
+        self.len = 0;
+        res
+    }
+}
+
+impl Write for Stdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.buf[self.len] = byte;
+            self.len += 1;
+            if byte == b'\n' || self.len == STDOUT_BUF_SIZE {
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Standard error, every write goes straight to the file
+pub struct Stderr;
+
+impl Write for Stderr {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_all(STDERR, s.as_bytes())
+    }
+}
+
+/// Write out whatever is left in the buffer of stdout. The runtime calls it before the program
+/// exits.
+pub fn flush() {
+    // A program that panicked while printing still holds the lock, its output is lost
+    if let Some(mut stdout) = STDOUT_WRITER.try_lock() {
+        let _ = stdout.flush();
+    }
+}
+
+/// Read a line from stdin into `line`, without the newline. A [`BACKSPACE`] erases the
+/// character before it, the way the console shows it. Returns `false` at the end of the input.
+pub fn read_line(line: &mut String) -> Result<bool, Errno> {
+    let mut bytes = Vec::new();
+    let mut chunk = [0u8; 64];
+    loop {
+        let read = read(STDIN, &mut chunk)?;
+        if read == 0 {
+            if bytes.is_empty() {
+                return Ok(false);
+            }
+            break;
+        }
+        let mut newline = false;
+        for &byte in &chunk[..read] {
+            match byte {
+                b'\n' => newline = true,
+                BACKSPACE => {
+                    bytes.pop();
+                }
+                byte => bytes.push(byte),
+            }
+        }
+        if newline {
+            break;
+        }
+    }
+    line.push_str(&String::from_utf8_lossy(&bytes));
+    Ok(true)
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_eprint(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    STDOUT_WRITER
+        .lock()
+        .write_fmt(args)
+        .expect("Couldn't write to stdout");
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    Stderr.write_fmt(args).expect("Couldn't write to stderr");
+}
@@ -1,4 +1,10 @@
-#![feature(asm_const)]
+#![feature(naked_functions)]
 #![no_std]
 
+extern crate alloc;
+
+pub mod heap;
+pub mod io;
+pub mod rt;
 pub mod syscalls;
+pub mod time;
@@ -0,0 +1,74 @@
+//! The runtime of user programs: the entry point that the kernel jumps to, and the panic
+//! handler. A program only defines `#[no_mangle] fn main() -> i32`, its return value is the exit
+//! code of the process.
+
+use crate::{
+    io::{self, Stderr},
+    syscalls::exit,
+};
+use core::{
+    fmt::Write,
+    panic::PanicInfo,
+    ptr::{addr_of_mut, write_bytes},
+    slice, str,
+};
+
+/// The exit code of a process that panicked
+pub const PANIC_EXIT_CODE: i32 = 101;
+
+extern "C" {
+    static mut __bss_start: u8;
+    static mut __bss_end: u8;
+}
+
+extern "Rust" {
+    fn main() -> i32;
+}
+
+/// The arguments `exec` passed to the program, see [`args`]
+static mut ARGS: &[(*const u8, usize)] = &[];
+
+/// The entry point of every user program (see `user.ld`). The kernel starts the program with
+/// `a0` holding the amount of arguments and `a1` pointing to them, which are passed on as is.
+#[naked]
+#[no_mangle]
+#[link_section = ".text.entry"]
+unsafe extern "C" fn _start() -> ! {
+    core::arch::asm!(
+        // `gp` can't be relaxed to itself
+        ".option push",
+        ".option norelax",
+        "la gp, __global_pointer$",
+        ".option pop",
+        "call {start_rust}",
+        start_rust = sym start_rust,
+        options(noreturn)
+    );
+}
+
+unsafe extern "C" fn start_rust(argc: usize, argv: *const (*const u8, usize)) -> ! {
+    let bss_start = addr_of_mut!(__bss_start);
+    let bss_len = addr_of_mut!(__bss_end) as usize - bss_start as usize;
+    write_bytes(bss_start, 0, bss_len);
+    if argc != 0 {
+        ARGS = slice::from_raw_parts(argv, argc);
+    }
+    let exit_code = main();
+    io::flush();
+    exit(exit_code as usize);
+}
+
+/// The arguments the program was started with
+pub fn args() -> impl Iterator<Item = &'static str> {
+    // The kernel copied valid strings onto the stack of the program, which is never unmapped
+    unsafe { ARGS }
+        .iter()
+        .map(|(ptr, len)| unsafe { str::from_utf8_unchecked(slice::from_raw_parts(*ptr, *len)) })
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    io::flush();
+    let _ = writeln!(Stderr, "User program panicked: {}", info);
+    exit(PANIC_EXIT_CODE as usize);
+}
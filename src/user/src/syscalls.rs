@@ -1,22 +1,552 @@
-use core::arch::asm;
-use kernel::syscall::*;
+use core::{arch::asm, mem::MaybeUninit, slice};
+use kernel::param::MAXARG;
+pub use kernel::{
+    errno::Errno,
+    files::fd::{
+        FileType, Stat, O_APPEND, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY, SEEK_CUR, SEEK_END,
+        SEEK_SET, STDERR, STDIN, STDOUT,
+    },
+    files::{dir::EMPTY_DIR_ENTRY, DirEntry},
+    mem::mmap::{MAP_ANONYMOUS, PROT_EXEC, PROT_NONE, PROT_READ, PROT_WRITE},
+    syscall::numbers::*,
+    time::{Timespec, CLOCK_MONOTONIC},
+};
+pub use kernel::{param::NPROC, proc::ProcInfo};
 
 pub fn print(x: &str) {
-    unsafe { sys_print(x.as_ptr(), x.len()) }
+    unsafe { sys_print(x.as_ptr(), x.len()) };
 }
 
-pub fn exit(exit_code: usize) {
+pub fn exit(exit_code: usize) -> ! {
     unsafe { sys_exit(exit_code) };
+    unreachable!("The process exited");
 }
 
-#[inline(never)]
-unsafe extern "C" fn sys_print(_ptr: *const u8, _len: usize) {
-    asm!("li a6, {sys}", sys = const PRINT_SYSCALL);
-    asm!("ecall");
+/// Duplicate the calling process. Returns 0 in the child, and the id of the child in the
+/// parent.
+pub fn fork() -> Result<usize, Errno> {
+    Errno::decode(unsafe { sys_fork() })
 }
 
-#[inline(never)]
-unsafe extern "C" fn sys_exit(_exit_code: usize) {
-    asm!("li a6, {sys}", sys = const EXIT_SYSCALL);
-    asm!("ecall");
+/// Replace the running program with the executable `path` from the disk, passing it `argv`.
+/// Only returns if the program couldn't be started.
+pub fn exec(path: &str, argv: &[&str]) -> Errno {
+    let mut args = [(core::ptr::null(), 0); MAXARG];
+    for (arg, a) in args.iter_mut().zip(argv) {
+        *arg = (a.as_ptr(), a.len());
+    }
+    let ret = unsafe { sys_exec(path.as_ptr(), path.len(), args.as_ptr(), argv.len()) };
+    Errno::decode(ret).unwrap_err()
+}
+
+/// Wait for any child process to exit. Returns its id and exit code, or `ECHILD` if the process
+/// has no children.
+pub fn wait() -> Result<(usize, usize), Errno> {
+    let (child, exit_code) = unsafe { sys_wait(usize::MAX) };
+    Errno::decode(child).map(|child| (child, exit_code))
+}
+
+/// Wait for the child process `pid` to exit. Returns its exit code, or `ECHILD` if `pid` isn't
+/// a child of the process.
+pub fn waitpid(pid: usize) -> Result<usize, Errno> {
+    let (child, exit_code) = unsafe { sys_wait(pid) };
+    Errno::decode(child).map(|_| exit_code)
+}
+
+/// Add `inc` to the nice value of the process, a higher nice value means a lower priority.
+/// The value is clamped to the priority levels of the scheduler. Returns the new nice value.
+pub fn nice(inc: isize) -> Result<usize, Errno> {
+    Errno::decode(unsafe { sys_nice(inc) })
+}
+
+/// Set the nice value of the process `pid`, or of the calling process if `pid` is `None`.
+/// Fails with `ESRCH` if there is no such process, or `EINVAL` if `nice` isn't a priority level.
+pub fn setpriority(pid: Option<usize>, nice: usize) -> Result<(), Errno> {
+    Errno::decode(unsafe { sys_setpriority(pid.unwrap_or(usize::MAX), nice) }).map(|_| ())
+}
+
+/// Pin the process `pid` (or the calling process if `pid` is `None`) to the harts that have
+/// their bit set in `mask`. Fails with `ESRCH` if there is no such process, or `EINVAL` if none
+/// of the harts in `mask` is running.
+pub fn sched_setaffinity(pid: Option<usize>, mask: usize) -> Result<(), Errno> {
+    Errno::decode(unsafe { sys_sched_setaffinity(pid.unwrap_or(usize::MAX), mask) }).map(|_| ())
+}
+
+/// The id of the calling process
+pub fn getpid() -> usize {
+    unsafe { sys_getpid() }
+}
+
+/// The id of the parent of the calling process
+pub fn getppid() -> usize {
+    unsafe { sys_getppid() }
+}
+
+/// Fill `procs` with a snapshot of the processes that are in use, and return the filled part.
+/// There might be more processes than fit in `procs`.
+pub fn ps(procs: &mut [MaybeUninit<ProcInfo>]) -> Result<&[ProcInfo], Errno> {
+    let len = Errno::decode(unsafe { sys_ps(procs.as_mut_ptr() as *mut ProcInfo, procs.len()) })?;
+    // The kernel initialized the first `len` entries
+    Ok(unsafe { slice::from_raw_parts(procs.as_ptr() as *const ProcInfo, len) })
+}
+
+/// Open the file `path` with the access mode in `flags` (one of [`O_RDONLY`], [`O_WRONLY`] and
+/// [`O_RDWR`]), combined with any of [`O_CREAT`], [`O_TRUNC`] and [`O_APPEND`]. Returns the new
+/// file descriptor.
+pub fn open(path: &str, flags: usize) -> Result<usize, Errno> {
+    Errno::decode(unsafe { sys_open(path.as_ptr(), path.len(), flags) })
+}
+
+/// Read from the file `fd` into `buf`. Returns how many bytes were read, 0 at the end of the
+/// file.
+pub fn read(fd: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+    Errno::decode(unsafe { sys_read(fd, buf.as_mut_ptr(), buf.len()) })
+}
+
+/// Write `buf` to the file `fd`. Returns how many bytes were written.
+pub fn write(fd: usize, buf: &[u8]) -> Result<usize, Errno> {
+    Errno::decode(unsafe { sys_write(fd, buf.as_ptr(), buf.len()) })
+}
+
+pub fn close(fd: usize) -> Result<(), Errno> {
+    Errno::decode(unsafe { sys_close(fd) }).map(|_| ())
+}
+
+/// Move the offset of the file `fd` to `offset` bytes from `whence` (one of [`SEEK_SET`],
+/// [`SEEK_CUR`] and [`SEEK_END`]). Returns the new offset.
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> Result<usize, Errno> {
+    Errno::decode(unsafe { sys_lseek(fd, offset, whence) })
+}
+
+pub fn fstat(fd: usize) -> Result<Stat, Errno> {
+    let mut stat = MaybeUninit::<Stat>::uninit();
+    Errno::decode(unsafe { sys_fstat(fd, stat.as_mut_ptr()) })?;
+    // The kernel filled the stat
+    Ok(unsafe { stat.assume_init() })
+}
+
+/// Delete the file `path`
+pub fn unlink(path: &str) -> Result<(), Errno> {
+    Errno::decode(unsafe { sys_unlink(path.as_ptr(), path.len()) }).map(|_| ())
+}
+
+/// Change the size of the file `fd` to `size` bytes. If it grows, the new part reads as zeros.
+pub fn ftruncate(fd: usize, size: usize) -> Result<(), Errno> {
+    Errno::decode(unsafe { sys_ftruncate(fd, size) }).map(|_| ())
+}
+
+/// Create an empty directory at `path`
+pub fn mkdir(path: &str) -> Result<(), Errno> {
+    Errno::decode(unsafe { sys_mkdir(path.as_ptr(), path.len()) }).map(|_| ())
+}
+
+/// Delete the directory `path`, which must be empty
+pub fn rmdir(path: &str) -> Result<(), Errno> {
+    Errno::decode(unsafe { sys_rmdir(path.as_ptr(), path.len()) }).map(|_| ())
+}
+
+/// Read the next entries of the directory `fd` (opened with [`O_RDONLY`]) into `entries`.
+/// Returns how many were read, 0 once there are no more. The kernel might read fewer than
+/// fit, even if there are more.
+pub fn readdir(fd: usize, entries: &mut [DirEntry]) -> Result<usize, Errno> {
+    Errno::decode(unsafe { sys_readdir(fd, entries.as_mut_ptr(), entries.len()) })
+}
+
+/// Change the working directory to `path`
+pub fn chdir(path: &str) -> Result<(), Errno> {
+    Errno::decode(unsafe { sys_chdir(path.as_ptr(), path.len()) }).map(|_| ())
+}
+
+/// Move the end of the heap to `addr`. Returns the new end of the heap, or `ENOMEM` if it
+/// couldn't be moved. `brk(0)` only returns the current end.
+pub fn brk(addr: usize) -> Result<usize, Errno> {
+    Errno::decode(unsafe { sys_brk(addr) })
+}
+
+/// Grow the heap by `inc` bytes, or shrink it if `inc` is negative. Returns the old end of the
+/// heap, which is where the new memory starts.
+pub fn sbrk(inc: isize) -> Result<usize, Errno> {
+    Errno::decode(unsafe { sys_sbrk(inc) })
+}
+
+/// Map `len` bytes (rounded up to whole pages) of zeroed memory with the protection `prot` (a
+/// combination of [`PROT_READ`], [`PROT_WRITE`] and [`PROT_EXEC`], or [`PROT_NONE`]). `flags`
+/// must be [`MAP_ANONYMOUS`]. The memory is put at `hint` if there is room there, and the
+/// address it was put at is returned.
+pub fn mmap(hint: usize, len: usize, prot: usize, flags: usize) -> Result<*mut u8, Errno> {
+    Errno::decode(unsafe { sys_mmap(hint, len, prot, flags) }).map(|addr| addr as *mut u8)
+}
+
+/// Unmap the pages of `addr..addr + len`, which were mapped with [`mmap`].
+pub fn munmap(addr: *mut u8, len: usize) -> Result<(), Errno> {
+    Errno::decode(unsafe { sys_munmap(addr as usize, len) }).map(|_| ())
+}
+
+/// Change the protection of the pages of `addr..addr + len` to `prot`, see [`mmap`]. Fails with
+/// `ENOMEM` if any of the pages isn't mapped.
+pub fn mprotect(addr: *mut u8, len: usize, prot: usize) -> Result<(), Errno> {
+    Errno::decode(unsafe { sys_mprotect(addr as usize, len, prot) }).map(|_| ())
+}
+
+/// The timer ticks since boot
+pub fn uptime() -> usize {
+    unsafe { sys_uptime() }
+}
+
+/// The current time of the clock `clock_id`, only [`CLOCK_MONOTONIC`] is supported.
+pub fn clock_gettime(clock_id: usize) -> Result<Timespec, Errno> {
+    let mut tp = Timespec::default();
+    Errno::decode(unsafe { sys_clock_gettime(clock_id, &mut tp) })?;
+    Ok(tp)
+}
+
+/// Block the process for at least `duration`. The kernel checks the deadline on every timer
+/// tick, so the process can oversleep by up to a tick.
+pub fn nanosleep(duration: &Timespec) -> Result<(), Errno> {
+    Errno::decode(unsafe { sys_nanosleep(duration) }).map(|_| ())
+}
+
+unsafe fn sys_print(ptr: *const u8, len: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") PRINT_SYSCALL,
+        inlateout("a0") ptr as usize => ret,
+        in("a1") len,
+    );
+    ret
+}
+
+unsafe fn sys_exit(exit_code: usize) {
+    asm!(
+        "ecall",
+        in("a6") EXIT_SYSCALL,
+        in("a0") exit_code,
+    );
+}
+
+unsafe fn sys_fork() -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") FORK_SYSCALL,
+        lateout("a0") ret,
+    );
+    ret
+}
+
+unsafe fn sys_exec(
+    path: *const u8,
+    path_len: usize,
+    argv: *const (*const u8, usize),
+    argc: usize,
+) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") EXEC_SYSCALL,
+        inlateout("a0") path as usize => ret,
+        in("a1") path_len,
+        in("a2") argv as usize,
+        in("a3") argc,
+    );
+    ret
+}
+
+unsafe fn sys_nice(inc: isize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") NICE_SYSCALL,
+        inlateout("a0") inc as usize => ret,
+    );
+    ret
+}
+
+unsafe fn sys_setpriority(pid: usize, nice: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") SETPRIORITY_SYSCALL,
+        inlateout("a0") pid => ret,
+        in("a1") nice,
+    );
+    ret
+}
+
+unsafe fn sys_sched_setaffinity(pid: usize, mask: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") SCHED_SETAFFINITY_SYSCALL,
+        inlateout("a0") pid => ret,
+        in("a1") mask,
+    );
+    ret
+}
+
+unsafe fn sys_getpid() -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") GETPID_SYSCALL,
+        lateout("a0") ret,
+    );
+    ret
+}
+
+unsafe fn sys_getppid() -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") GETPPID_SYSCALL,
+        lateout("a0") ret,
+    );
+    ret
+}
+
+unsafe fn sys_ps(buf: *mut ProcInfo, cap: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") PS_SYSCALL,
+        inlateout("a0") buf as usize => ret,
+        in("a1") cap,
+    );
+    ret
+}
+
+unsafe fn sys_open(path: *const u8, path_len: usize, flags: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") OPEN_SYSCALL,
+        inlateout("a0") path as usize => ret,
+        in("a1") path_len,
+        in("a2") flags,
+    );
+    ret
+}
+
+unsafe fn sys_read(fd: usize, buf: *mut u8, len: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") READ_SYSCALL,
+        inlateout("a0") fd => ret,
+        in("a1") buf as usize,
+        in("a2") len,
+    );
+    ret
+}
+
+unsafe fn sys_write(fd: usize, buf: *const u8, len: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") WRITE_SYSCALL,
+        inlateout("a0") fd => ret,
+        in("a1") buf as usize,
+        in("a2") len,
+    );
+    ret
+}
+
+unsafe fn sys_close(fd: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") CLOSE_SYSCALL,
+        inlateout("a0") fd => ret,
+    );
+    ret
+}
+
+unsafe fn sys_lseek(fd: usize, offset: isize, whence: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") LSEEK_SYSCALL,
+        inlateout("a0") fd => ret,
+        in("a1") offset as usize,
+        in("a2") whence,
+    );
+    ret
+}
+
+unsafe fn sys_fstat(fd: usize, stat: *mut Stat) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") FSTAT_SYSCALL,
+        inlateout("a0") fd => ret,
+        in("a1") stat as usize,
+    );
+    ret
+}
+
+unsafe fn sys_brk(addr: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") BRK_SYSCALL,
+        inlateout("a0") addr => ret,
+    );
+    ret
+}
+
+unsafe fn sys_sbrk(inc: isize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") SBRK_SYSCALL,
+        inlateout("a0") inc as usize => ret,
+    );
+    ret
+}
+
+unsafe fn sys_mmap(hint: usize, len: usize, prot: usize, flags: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") MMAP_SYSCALL,
+        inlateout("a0") hint => ret,
+        in("a1") len,
+        in("a2") prot,
+        in("a3") flags,
+    );
+    ret
+}
+
+unsafe fn sys_munmap(addr: usize, len: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") MUNMAP_SYSCALL,
+        inlateout("a0") addr => ret,
+        in("a1") len,
+    );
+    ret
+}
+
+unsafe fn sys_mprotect(addr: usize, len: usize, prot: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") MPROTECT_SYSCALL,
+        inlateout("a0") addr => ret,
+        in("a1") len,
+        in("a2") prot,
+    );
+    ret
+}
+
+unsafe fn sys_uptime() -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") UPTIME_SYSCALL,
+        lateout("a0") ret,
+    );
+    ret
+}
+
+unsafe fn sys_clock_gettime(clock_id: usize, tp: *mut Timespec) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") CLOCK_GETTIME_SYSCALL,
+        inlateout("a0") clock_id => ret,
+        in("a1") tp as usize,
+    );
+    ret
+}
+
+unsafe fn sys_nanosleep(req: *const Timespec) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") NANOSLEEP_SYSCALL,
+        inlateout("a0") req as usize => ret,
+    );
+    ret
+}
+
+/// The kernel returns two values, so the registers are set explicitly
+unsafe fn sys_wait(pid: usize) -> (usize, usize) {
+    let (child, exit_code): (usize, usize);
+    asm!(
+        "ecall",
+        in("a6") WAIT_SYSCALL,
+        inlateout("a0") pid => child,
+        lateout("a1") exit_code,
+    );
+    (child, exit_code)
+}
+
+unsafe fn sys_unlink(path: *const u8, path_len: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") UNLINK_SYSCALL,
+        inlateout("a0") path as usize => ret,
+        in("a1") path_len,
+    );
+    ret
+}
+
+unsafe fn sys_ftruncate(fd: usize, size: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") FTRUNCATE_SYSCALL,
+        inlateout("a0") fd => ret,
+        in("a1") size,
+    );
+    ret
+}
+
+unsafe fn sys_mkdir(path: *const u8, path_len: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") MKDIR_SYSCALL,
+        inlateout("a0") path as usize => ret,
+        in("a1") path_len,
+    );
+    ret
+}
+
+unsafe fn sys_rmdir(path: *const u8, path_len: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") RMDIR_SYSCALL,
+        inlateout("a0") path as usize => ret,
+        in("a1") path_len,
+    );
+    ret
+}
+
+unsafe fn sys_readdir(fd: usize, entries: *mut DirEntry, count: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") READDIR_SYSCALL,
+        inlateout("a0") fd => ret,
+        in("a1") entries as usize,
+        in("a2") count,
+    );
+    ret
+}
+
+unsafe fn sys_chdir(path: *const u8, path_len: usize) -> usize {
+    let ret: usize;
+    asm!(
+        "ecall",
+        in("a6") CHDIR_SYSCALL,
+        inlateout("a0") path as usize => ret,
+        in("a1") path_len,
+    );
+    ret
 }
@@ -0,0 +1,33 @@
+//! Time for user programs, measured on the monotonic clock of the kernel (the time since boot).
+
+use crate::syscalls::{clock_gettime, nanosleep, Timespec, CLOCK_MONOTONIC};
+pub use core::time::Duration;
+
+/// A point in time, for measuring how long something took
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Instant(Duration);
+
+impl Instant {
+    pub fn now() -> Self {
+        let now = clock_gettime(CLOCK_MONOTONIC).expect("The monotonic clock is supported");
+        Instant(Duration::new(now.tv_sec, now.tv_nsec as u32))
+    }
+
+    /// The time from `earlier` to `self`, or zero if `earlier` is later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+/// Block the process for at least `duration`, give or take a timer tick.
+pub fn sleep(duration: Duration) {
+    let req = Timespec {
+        tv_sec: duration.as_secs(),
+        tv_nsec: duration.subsec_nanos() as u64,
+    };
+    nanosleep(&req).expect("The nanoseconds are below a second");
+}